@@ -0,0 +1,196 @@
+// Minimal OpenID Connect provider for trying the OIDC login flow locally.
+//
+//   cargo run --example mock_idp
+//
+// Then start the backend with
+//   OIDC_ISSUER=http://127.0.0.1:9000 OIDC_CLIENT_ID=todo-app
+//   OIDC_REDIRECT_URI=http://127.0.0.1:3001/oidc/callback
+// and open http://127.0.0.1:3001/oidc/login. Every authorization request is
+// approved at once for the subject in MOCK_IDP_SUBJECT (default "mock-user").
+use axum::{
+    extract::{Extension, Form, Query},
+    http::StatusCode,
+    response::{IntoResponse, Redirect},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rand::rngs::OsRng;
+use rsa::{pkcs8::EncodePrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const ISSUER: &str = "http://127.0.0.1:9000";
+const KID: &str = "mock-key";
+
+struct PendingCode {
+    client_id: String,
+    redirect_uri: String,
+    nonce: Option<String>,
+    code_challenge: String,
+}
+
+struct Idp {
+    encoding_key: EncodingKey,
+    jwk: serde_json::Value,
+    subject: String,
+    codes: Mutex<HashMap<String, PendingCode>>,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeParams {
+    client_id: String,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: String,
+    code_challenge_method: String,
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+async fn discovery() -> impl IntoResponse {
+    Json(json!({
+        "issuer": ISSUER,
+        "authorization_endpoint": format!("{}/authorize", ISSUER),
+        "token_endpoint": format!("{}/token", ISSUER),
+        "jwks_uri": format!("{}/jwks", ISSUER),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+        "code_challenge_methods_supported": ["S256"]
+    }))
+}
+
+async fn jwks(Extension(idp): Extension<Arc<Idp>>) -> impl IntoResponse {
+    Json(json!({ "keys": [idp.jwk] }))
+}
+
+async fn authorize(
+    Extension(idp): Extension<Arc<Idp>>,
+    Query(params): Query<AuthorizeParams>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if params.code_challenge_method != "S256" {
+        return Err((StatusCode::BAD_REQUEST, "only S256 PKCE is supported".to_string()));
+    }
+
+    let code: String = (0..4).map(|_| format!("{:08x}", rand::random::<u32>())).collect();
+    idp.codes.lock().unwrap().insert(
+        code.clone(),
+        PendingCode {
+            client_id: params.client_id,
+            redirect_uri: params.redirect_uri.clone(),
+            nonce: params.nonce,
+            code_challenge: params.code_challenge,
+        },
+    );
+
+    let mut url = append_query(&params.redirect_uri, "code", &code);
+    if let Some(state) = params.state {
+        url = append_query(&url, "state", &state);
+    }
+    Ok(Redirect::to(&url))
+}
+
+// Append a query parameter; codes and states are URL-safe already
+fn append_query(base: &str, key: &str, value: &str) -> String {
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!("{}{}{}={}", base, separator, key, value)
+}
+
+async fn token(
+    Extension(idp): Extension<Arc<Idp>>,
+    Form(form): Form<TokenForm>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if form.grant_type != "authorization_code" {
+        return Err((StatusCode::BAD_REQUEST, "unsupported_grant_type".to_string()));
+    }
+    let pending = idp
+        .codes
+        .lock()
+        .unwrap()
+        .remove(&form.code)
+        .ok_or((StatusCode::BAD_REQUEST, "invalid_grant".to_string()))?;
+
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form.code_verifier.as_bytes()));
+    if challenge != pending.code_challenge
+        || form.client_id != pending.client_id
+        || form.redirect_uri != pending.redirect_uri
+    {
+        return Err((StatusCode::BAD_REQUEST, "invalid_grant".to_string()));
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let claims = json!({
+        "iss": ISSUER,
+        "sub": idp.subject,
+        "aud": pending.client_id,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": pending.nonce,
+        "email": format!("{}@example.com", idp.subject),
+        "preferred_username": idp.subject,
+    });
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(KID.to_string());
+    let id_token = encode(&header, &claims, &idp.encoding_key)
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(Json(json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token
+    })))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let private_key = RsaPrivateKey::new(&mut OsRng, 2048)?;
+    let pem = private_key.to_pkcs8_pem(rsa::pkcs8::LineEnding::LF)?;
+    let jwk = json!({
+        "kty": "RSA",
+        "kid": KID,
+        "alg": "RS256",
+        "use": "sig",
+        "n": URL_SAFE_NO_PAD.encode(private_key.n().to_bytes_be()),
+        "e": URL_SAFE_NO_PAD.encode(private_key.e().to_bytes_be()),
+    });
+
+    let idp = Arc::new(Idp {
+        encoding_key: EncodingKey::from_rsa_pem(pem.as_bytes())?,
+        jwk,
+        subject: env::var("MOCK_IDP_SUBJECT").unwrap_or_else(|_| "mock-user".to_string()),
+        codes: Mutex::new(HashMap::new()),
+    });
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .route("/jwks", get(jwks))
+        .layer(Extension(idp));
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 9000));
+    println!("Mock OpenID provider running at http://{}", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Bumping a user's generation invalidates every access token issued before it
+ALTER TABLE users
+ADD COLUMN token_generation INT NOT NULL DEFAULT 0;
+
+-- Access tokens revoked before their exp; rows can be dropped once expired
+CREATE TABLE revoked_tokens (
+    jti TEXT PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,18 @@
+-- Add migration script here
+ALTER TABLE users
+ADD COLUMN totp_secret TEXT,
+ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+-- Last accepted time step, so a code cannot be used twice
+ADD COLUMN totp_last_step BIGINT,
+-- Wrong codes in a row, and when codes are accepted again after too many
+ADD COLUMN totp_failures INT NOT NULL DEFAULT 0,
+ADD COLUMN totp_locked_until TIMESTAMPTZ;
+
+CREATE TABLE recovery_codes (
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
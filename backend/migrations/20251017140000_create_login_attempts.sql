@@ -0,0 +1,17 @@
+-- Add migration script here
+-- Failed login tracking, keyed by "user:<username>" or "ip:<address>", and
+-- password reset requests under the same keys prefixed with "reset:"
+CREATE TABLE login_attempts (
+    key TEXT PRIMARY KEY,
+    failures INT NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMPTZ
+);
+
+-- Stale rows are pruned by age whenever a failure is recorded
+CREATE INDEX login_attempts_last_failure_at_idx ON login_attempts (last_failure_at);
+
+-- Wrong two-factor codes now count against the "user:" key instead
+ALTER TABLE users
+DROP COLUMN totp_failures,
+DROP COLUMN totp_locked_until;
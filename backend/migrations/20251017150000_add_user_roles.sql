@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Promote the first administrator by hand:
+--   UPDATE users SET role = 'admin' WHERE username = '...';
+ALTER TABLE users
+ADD COLUMN role TEXT NOT NULL DEFAULT 'user' CHECK (role IN ('user', 'admin')),
+ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE,
+-- Set by an admin; login is refused until the user completes a password reset
+ADD COLUMN password_reset_required BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Whether the callback should start a cookie session instead of returning tokens
+ALTER TABLE oidc_login_states
+ADD COLUMN use_cookies BOOLEAN NOT NULL DEFAULT FALSE;
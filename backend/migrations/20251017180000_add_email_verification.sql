@@ -0,0 +1,19 @@
+-- Add migration script here
+ALTER TABLE users
+ADD COLUMN email TEXT,
+ADD COLUMN email_verified_at TIMESTAMPTZ;
+
+-- Addresses are compared case-insensitively
+CREATE UNIQUE INDEX users_email_key ON users (LOWER(email));
+
+CREATE TABLE email_verification_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- The address being verified, so a token cannot confirm a later change
+    email TEXT NOT NULL,
+    -- SHA-256 of the token sent by mail
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
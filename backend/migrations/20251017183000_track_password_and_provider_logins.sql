@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Accounts provisioned through OpenID Connect start with a random password
+-- nobody knows, until one is set through a reset link
+ALTER TABLE users
+ADD COLUMN password_set BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Last time the identity was used to sign in, so the provider can stand in
+-- for the password when such an account confirms a sensitive action
+ALTER TABLE user_identities
+ADD COLUMN last_login_at TIMESTAMPTZ;
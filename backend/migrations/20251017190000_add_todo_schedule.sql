@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE todos
+ADD COLUMN due_at TIMESTAMPTZ,
+ADD COLUMN start_at TIMESTAMPTZ,
+ADD CONSTRAINT todos_start_before_due CHECK (start_at IS NULL OR due_at IS NULL OR start_at <= due_at);
+
+CREATE INDEX todos_user_id_due_at_idx ON todos (user_id, due_at);
+
+-- IANA zone name used to evaluate "today" and "this week" for the user
+ALTER TABLE users
+ADD COLUMN timezone TEXT NOT NULL DEFAULT 'UTC';
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE todos
+ADD COLUMN priority TEXT NOT NULL DEFAULT 'none'
+    CHECK (priority IN ('none', 'low', 'medium', 'high', 'urgent'));
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Existing rows get the migration time; when they were completed is unknown
+ALTER TABLE todos
+ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+ADD COLUMN completed_at TIMESTAMPTZ;
+
+CREATE INDEX todos_user_id_created_at_idx ON todos (user_id, created_at);
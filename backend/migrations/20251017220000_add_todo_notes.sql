@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Markdown source; HTML is rendered on request and never stored
+ALTER TABLE todos
+ADD COLUMN notes TEXT;
@@ -0,0 +1,17 @@
+-- Add migration script here
+CREATE TABLE projects (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Project names are unique per user, ignoring case
+CREATE UNIQUE INDEX projects_user_id_name_key ON projects (user_id, lower(name));
+
+-- Todos without a project are in the inbox. Deleting a project moves its
+-- todos there unless the API is asked to delete them as well.
+ALTER TABLE todos
+ADD COLUMN project_id INTEGER REFERENCES projects(id) ON DELETE SET NULL;
+
+CREATE INDEX todos_project_id_idx ON todos (project_id);
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Deleting a todo deletes its subtasks
+ALTER TABLE todos
+ADD COLUMN parent_id INTEGER REFERENCES todos(id) ON DELETE CASCADE,
+ADD CONSTRAINT todos_not_own_parent CHECK (parent_id <> id);
+
+CREATE INDEX todos_parent_id_idx ON todos (parent_id);
+
+-- How completing a todo affects the rest of its tree
+ALTER TABLE users
+ADD COLUMN complete_children_with_parent BOOLEAN NOT NULL DEFAULT TRUE,
+ADD COLUMN auto_complete_parents BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Sort key within a list (same user, project and parent), compared bytewise.
+-- Existing todos keep their creation order.
+ALTER TABLE todos
+ADD COLUMN position TEXT COLLATE "C";
+
+UPDATE todos SET position = lpad(id::text, 10, '0');
+
+ALTER TABLE todos
+ALTER COLUMN position SET NOT NULL;
+
+CREATE INDEX todos_list_position_idx ON todos (user_id, project_id, parent_id, position);
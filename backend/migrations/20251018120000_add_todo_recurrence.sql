@@ -0,0 +1,8 @@
+-- Add migration script here
+-- RFC 5545 RRULE, e.g. FREQ=WEEKLY;BYDAY=TU. Only the newest open todo of a
+-- series carries the rule; recurrence_start is the due date of the first
+-- occurrence, which COUNT and the rule's day pattern are measured from.
+ALTER TABLE todos
+ADD COLUMN recurrence TEXT,
+ADD COLUMN recurrence_start TIMESTAMPTZ,
+ADD CONSTRAINT todos_recurrence_needs_due CHECK (recurrence IS NULL OR due_at IS NOT NULL);
@@ -0,0 +1,234 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use sqlx::{Pool, Postgres};
+use crate::auth::{self, AuthenticatedUser};
+use crate::error::ApiError;
+use crate::extract::Path;
+use crate::handlers::send_password_reset;
+use crate::mailer::Mailer;
+use crate::models::AdminUserView;
+
+// Routes under /admin, guarded by authentication and the admin role
+pub fn router() -> Router {
+    Router::new()
+        .route("/admin/users", get(list_users_handler))
+        .route("/admin/users/:id", delete(delete_user_handler))
+        .route("/admin/users/:id/disable", post(disable_user_handler))
+        .route("/admin/users/:id/enable", post(enable_user_handler))
+        .route("/admin/users/:id/force-password-reset", post(force_password_reset_handler))
+        // Layers run bottom-up: authenticate first, then check the role
+        .layer(middleware::from_fn(auth::require_admin))
+        .layer(middleware::from_fn(auth::require_auth))
+}
+
+// Admins cannot lock themselves out through the admin API
+async fn reject_self(
+    pool: &Pool<Postgres>,
+    auth_user: &AuthenticatedUser,
+    id: i32,
+) -> Result<(), ApiError> {
+    let own_id = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = $1")
+        .bind(&auth_user.username)
+        .fetch_one(pool)
+        .await?;
+
+    if own_id == id {
+        Err(ApiError::BadRequest("Admins cannot perform this action on their own account".to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// List all users
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    responses(
+        (status = 200, description = "List of users", body = [AdminUserView]),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Admin role required", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn list_users_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let users = sqlx::query_as::<_, AdminUserView>(
+        "SELECT id, username, role, disabled, totp_enabled, password_reset_required
+         FROM users
+         ORDER BY id"
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(users))
+}
+
+/// Disable a user account
+///
+/// The user can no longer log in and all of their sessions and personal
+/// access tokens stop working.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/disable",
+    params(
+        ("id" = i32, Path, description = "User ID")
+    ),
+    responses(
+        (status = 204, description = "User disabled"),
+        (status = 400, description = "Cannot disable your own account", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Admin role required", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "User not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn disable_user_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    reject_self(&pool, &auth_user, id).await?;
+
+    let username = sqlx::query_scalar::<_, String>(
+        "UPDATE users SET disabled = TRUE WHERE id = $1 RETURNING username"
+    )
+    .bind(id)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("User with id {} not found", id)))?;
+
+    auth::revoke_all_sessions(&pool, &username).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Re-enable a disabled user account
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/enable",
+    params(
+        ("id" = i32, Path, description = "User ID")
+    ),
+    responses(
+        (status = 204, description = "User enabled"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Admin role required", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "User not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn enable_user_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    let result = sqlx::query("UPDATE users SET disabled = FALSE WHERE id = $1")
+        .bind(id)
+        .execute(&pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        Err(ApiError::NotFound(format!("User with id {} not found", id)))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+/// Force a user to reset their password
+///
+/// Logs the user out everywhere, deletes their personal access tokens, blocks
+/// login until the password is reset and mails them a reset link if they have
+/// a verified email address.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/force-password-reset",
+    params(
+        ("id" = i32, Path, description = "User ID")
+    ),
+    responses(
+        (status = 204, description = "Password reset forced"),
+        (status = 400, description = "Cannot force a reset of your own account", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Admin role required", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "User not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn force_password_reset_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(mailer): Extension<Mailer>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    reject_self(&pool, &auth_user, id).await?;
+
+    let username = sqlx::query_scalar::<_, String>(
+        "UPDATE users SET password_reset_required = TRUE WHERE id = $1 RETURNING username"
+    )
+    .bind(id)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("User with id {} not found", id)))?;
+
+    auth::revoke_all_sessions(&pool, &username).await?;
+    send_password_reset(&pool, &mailer, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a user and all of their data
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{id}",
+    params(
+        ("id" = i32, Path, description = "User ID")
+    ),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 400, description = "Cannot delete your own account", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Admin role required", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "User not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn delete_user_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    reject_self(&pool, &auth_user, id).await?;
+
+    // Todos, tokens and codes are removed by ON DELETE CASCADE.
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(id)
+        .execute(&pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        Err(ApiError::NotFound(format!("User with id {} not found", id)))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
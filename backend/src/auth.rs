@@ -1,95 +1,424 @@
 use axum::{
     async_trait,
     extract::FromRequestParts,
-    http::{StatusCode, request::Parts, Request, Response,header},
+    http::{request::Parts, Request, Response, HeaderMap, header},
     middleware::Next,
     body::BoxBody,  // Add this import
 };
-use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey, TokenData};
+use jsonwebtoken::TokenData;
 use serde::{Serialize, Deserialize};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
 use once_cell::sync::Lazy;
-use std::env;
-
-pub static JWT_SECRET: Lazy<String> = Lazy::new(|| {
-    env::var("JWT_SECRET").expect("JWT_SECRET must be set in .env")
-});
+use crate::error::ApiError;
+use crate::keys::KEYRING;
+use crate::models::Role;
+use crate::session;
+use crate::totp;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String, // subject (username)
     pub exp: usize,  // expiration timestamp
+    pub jti: String, // unique token id, used for revocation
+    pub gen: i32,    // user's token generation at issue time
+    pub role: Role,
+    pub aud: String, // always ACCESS_TOKEN_AUDIENCE
 }
 
-// Create JWT valid for 1 hour
-pub fn create_jwt(username: &str) -> Result<String, jsonwebtoken::errors::Error> {
+// Claims of the short-lived token handed out between password and 2FA code
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub sub: String, // subject (username)
+    pub exp: usize,  // expiration timestamp
+    pub aud: String, // always CHALLENGE_AUDIENCE
+}
+
+// Every token is signed with the keys published at /.well-known/jwks.json, so
+// the audience is what tells an access token apart from a 2FA challenge.
+// Services trusting the JWKS must require ACCESS_TOKEN_AUDIENCE.
+pub const ACCESS_TOKEN_AUDIENCE: &str = "todo-api";
+pub const CHALLENGE_AUDIENCE: &str = "todo-api:2fa-challenge";
+
+// Unix timestamp `seconds` from now
+fn expires_in(seconds: u64) -> usize {
     use std::time::{SystemTime, UNIX_EPOCH};
     let expiration = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_secs() + 3600; // 1 hour
+        .as_secs() + seconds;
+    expiration as usize
+}
 
+// Create JWT valid for 1 hour
+pub fn create_jwt(username: &str, token_generation: i32, role: Role) -> Result<String, jsonwebtoken::errors::Error> {
     let claims = Claims {
         sub: username.to_owned(),
-        exp: expiration as usize,
+        exp: expires_in(3600), // 1 hour
+        jti: generate_token(),
+        gen: token_generation,
+        role,
+        aud: ACCESS_TOKEN_AUDIENCE.to_owned(),
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET.as_bytes()),
-    )
+    KEYRING.sign(&claims)
 }
 
 // Verify JWT token
 pub fn verify_jwt(token: &str) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(JWT_SECRET.as_bytes()),
-        &Validation::default(),
+    KEYRING.verify::<Claims>(token, ACCESS_TOKEN_AUDIENCE)
+}
+
+// Personal access tokens are told apart from JWTs by this prefix
+pub const PAT_PREFIX: &str = "pat_";
+
+// Scopes a personal access token can be granted
+pub const SCOPE_TODOS_READ: &str = "todos:read";
+pub const SCOPE_TODOS_WRITE: &str = "todos:write";
+pub const SCOPES: [&str; 2] = [SCOPE_TODOS_READ, SCOPE_TODOS_WRITE];
+
+// Create a 2FA challenge token valid for 5 minutes. Its audience differs from
+// that of access tokens, so it can never be accepted as one, here or by a
+// service verifying tokens against the JWKS.
+pub fn create_challenge_token(username: &str) -> Result<String, jsonwebtoken::errors::Error> {
+    let claims = ChallengeClaims {
+        sub: username.to_owned(),
+        exp: expires_in(300), // 5 minutes
+        aud: CHALLENGE_AUDIENCE.to_owned(),
+    };
+
+    KEYRING.sign(&claims)
+}
+
+// Verify a 2FA challenge token and return the username it was issued for
+pub fn verify_challenge_token(token: &str) -> Option<String> {
+    KEYRING
+        .verify::<ChallengeClaims>(token, CHALLENGE_AUDIENCE)
+        .ok()
+        .map(|data| data.claims.sub)
+}
+
+// Check a TOTP code or, failing that, an unused recovery code for the user.
+// A matching TOTP step or recovery code is consumed so it cannot be replayed.
+pub async fn verify_second_factor(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    secret: &str,
+    last_step: Option<i64>,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    if let Some(step) = totp::verify_code(secret, code, last_step) {
+        let result = sqlx::query(
+            "UPDATE users SET totp_last_step = $2
+             WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)"
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(pool)
+        .await?;
+        return Ok(result.rows_affected() == 1);
+    }
+
+    let codes = sqlx::query_as::<_, (i32, String, bool)>(
+        "SELECT id, code_hash, used_at IS NOT NULL FROM recovery_codes WHERE user_id = $1"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let Some(code_id) = totp::find_recovery_code(&hash_token(&totp::normalize_recovery_code(code)), &codes) else {
+        return Ok(false);
+    };
+
+    // Claim the code so two concurrent logins cannot both use it
+    let result = sqlx::query("UPDATE recovery_codes SET used_at = NOW() WHERE id = $1 AND used_at IS NULL")
+        .bind(code_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Refresh tokens live for 30 days and are rotated on every use
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+// Password reset links expire after an hour
+pub const PASSWORD_RESET_TTL_MINUTES: i32 = 60;
+
+// Email verification links expire after a day
+pub const EMAIL_VERIFICATION_TTL_HOURS: i32 = 24;
+
+// A sign-in with the identity provider counts as re-authentication for ten minutes
+pub const REAUTH_WINDOW_MINUTES: i32 = 10;
+
+// What an account with an unverified email address may do, from
+// EMAIL_VERIFICATION_POLICY=none|login|write (default none). Accounts
+// without an address are not affected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailVerificationPolicy {
+    // Unverified addresses are not restricted
+    None,
+    // No tokens are issued until the address is verified
+    Login,
+    // Logging in works, but todos cannot be changed
+    Write,
+}
+
+pub static EMAIL_VERIFICATION_POLICY: Lazy<EmailVerificationPolicy> = Lazy::new(|| {
+    match std::env::var("EMAIL_VERIFICATION_POLICY").as_deref() {
+        Ok("login") => EmailVerificationPolicy::Login,
+        Ok("write") => EmailVerificationPolicy::Write,
+        _ => EmailVerificationPolicy::None,
+    }
+});
+
+// Generate a random opaque token (used for refresh tokens and their family ids)
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
+
+// Only the SHA-256 of an opaque token is stored in the database
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Store a new refresh token for the user and return the raw value.
+// Passing an existing family id continues a rotation chain; `None` starts a new one.
+pub async fn issue_refresh_token(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    family_id: Option<&str>,
+) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+    let family_id = family_id.map(str::to_owned).unwrap_or_else(generate_token);
+
+    sqlx::query(
+        "INSERT INTO refresh_tokens (user_id, token_hash, family_id, expires_at)
+         VALUES ($1, $2, $3, NOW() + make_interval(days => $4))"
     )
+    .bind(user_id)
+    .bind(hash_token(&token))
+    .bind(family_id)
+    .bind(REFRESH_TOKEN_TTL_DAYS as i32)
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+// Revoke every token of a rotation chain, e.g. after a used token was replayed
+pub async fn revoke_refresh_family(pool: &Pool<Postgres>, family_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = NOW()
+         WHERE family_id = $1 AND revoked_at IS NULL"
+    )
+    .bind(family_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Invalidate every access, refresh and personal access token the user holds
+pub async fn revoke_all_sessions(pool: &Pool<Postgres>, username: &str) -> Result<(), sqlx::Error> {
+    let user_id = sqlx::query_scalar::<_, i32>(
+        "UPDATE users SET token_generation = token_generation + 1
+         WHERE username = $1
+         RETURNING id"
+    )
+    .bind(username)
+    .fetch_one(pool)
+    .await?;
+
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = NOW()
+         WHERE user_id = $1 AND revoked_at IS NULL"
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DELETE FROM personal_access_tokens WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Resolve a Bearer token, which is either a personal access token or a session JWT
+pub async fn authenticate(pool: &Pool<Postgres>, token: &str) -> Result<AuthenticatedUser, ApiError> {
+    if token.starts_with(PAT_PREFIX) {
+        authenticate_pat(pool, token).await
+    } else {
+        authenticate_jwt(pool, token).await
+    }
+}
+
+// Look up a personal access token by hash and record that it was used. Like
+// sessions, tokens stop working while the account is disabled or waiting for a
+// forced password reset.
+async fn authenticate_pat(pool: &Pool<Postgres>, token: &str) -> Result<AuthenticatedUser, ApiError> {
+    let (username, role, scopes, email_verified) = sqlx::query_as::<_, (String, String, Vec<String>, bool)>(
+        "UPDATE personal_access_tokens p SET last_used_at = NOW()
+         FROM users u
+         WHERE p.token_hash = $1 AND p.user_id = u.id
+         AND NOT u.disabled AND NOT u.password_reset_required
+         RETURNING u.username, u.role, p.scopes,
+                   u.email IS NULL OR u.email_verified_at IS NOT NULL"
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(invalid_token)?;
+
+    Ok(AuthenticatedUser {
+        username,
+        role: Role::from_db(&role),
+        credential: Credential::PersonalAccessToken { scopes },
+        email_verified,
+    })
+}
+
+// Check a signed JWT against the revocation list and the user's token generation
+async fn authenticate_jwt(pool: &Pool<Postgres>, token: &str) -> Result<AuthenticatedUser, ApiError> {
+    let claims = verify_jwt(token)
+        .map_err(|_| invalid_token())?
+        .claims;
+
+    let (token_generation, role, disabled, revoked, email_verified) = sqlx::query_as::<_, (i32, String, bool, bool, bool)>(
+        "SELECT u.token_generation, u.role, u.disabled,
+                EXISTS (SELECT 1 FROM revoked_tokens r WHERE r.jti = $2),
+                u.email IS NULL OR u.email_verified_at IS NOT NULL
+         FROM users u
+         WHERE u.username = $1"
+    )
+    .bind(&claims.sub)
+    .bind(&claims.jti)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(invalid_token)?;
+
+    // A role change also invalidates tokens that still carry the old role.
+    if revoked || disabled || claims.gen != token_generation || claims.role != Role::from_db(&role) {
+        return Err(invalid_token());
+    }
+
+    Ok(AuthenticatedUser {
+        username: claims.sub,
+        role: claims.role,
+        credential: Credential::Session {
+            jti: claims.jti,
+            exp: claims.exp,
+        },
+        email_verified,
+    })
+}
+
+fn invalid_token() -> ApiError {
+    ApiError::Unauthorized("Invalid or expired token".to_string())
+}
+
+// Extract the token from an `Authorization: Bearer <token>` header
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
 }
 
 // Auth middleware function
 pub async fn require_auth<B>(
     mut req: Request<B>, 
     next: Next<B>
-) -> Result<Response<BoxBody>, StatusCode>
+) -> Result<Response<BoxBody>, ApiError>
 where
     B: Send,
 {
-    let auth_header = req
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|header| header.to_str().ok())
-        .and_then(|value| {
-            if value.starts_with("Bearer ") {
-                Some(value[7..].trim())
-            } else {
-                None
-            }
-        });
-
-    if let Some(token) = auth_header {
-        match verify_jwt(token) {
-            Ok(token_data) => {
-                // Extract the username from token and add to request extensions
-                req.extensions_mut().insert(AuthenticatedUser {
-                    username: token_data.claims.sub,
-                });
-                let response = next.run(req).await;
-                Ok(response)
-            },
-            Err(_) => Err(StatusCode::UNAUTHORIZED),
-        }
-    } else {
-        Err(StatusCode::UNAUTHORIZED)
+    let pool = req
+        .extensions()
+        .get::<Pool<Postgres>>()
+        .cloned()
+        .ok_or_else(|| ApiError::Internal("Database pool missing from request extensions".to_string()))?;
+    let token = session::request_token(req.method(), req.headers())?;
+
+    // Add the authenticated user to request extensions
+    let user = authenticate(&pool, &token).await?;
+    req.extensions_mut().insert(user);
+    let response = next.run(req).await;
+    Ok(response)
+}
+
+// Layer for the admin router; must run after `require_auth`.
+// Personal access tokens are never accepted here, whatever their owner's role.
+pub async fn require_admin<B>(
+    req: Request<B>,
+    next: Next<B>
+) -> Result<Response<BoxBody>, ApiError>
+where
+    B: Send,
+{
+    let user = req
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .ok_or_else(|| ApiError::Unauthorized("Missing credentials".to_string()))?;
+
+    match (&user.role, &user.credential) {
+        (Role::Admin, Credential::Session { .. }) => Ok(next.run(req).await),
+        _ => Err(ApiError::Forbidden("Admin role required".to_string())),
     }
 }
 
+// How the current request was authenticated
+#[derive(Debug, Clone)]
+pub enum Credential {
+    // Interactive login; the jti lets the token be revoked on logout
+    Session { jti: String, exp: usize },
+    // Personal access token, limited to its scopes
+    PersonalAccessToken { scopes: Vec<String> },
+}
+
 // For extracting user info in handlers
 #[derive(Debug, Clone)]
 pub struct AuthenticatedUser {
     pub username: String,
+    pub role: Role,
+    pub credential: Credential,
+    // False while the account has an email address that is not verified yet
+    pub email_verified: bool,
+}
+
+impl AuthenticatedUser {
+    // Sessions may do anything; personal access tokens only what they were granted
+    pub fn require_scope(&self, scope: &str) -> Result<(), ApiError> {
+        match &self.credential {
+            Credential::Session { .. } => Ok(()),
+            Credential::PersonalAccessToken { scopes } if scopes.iter().any(|s| s == scope) => Ok(()),
+            Credential::PersonalAccessToken { .. } => {
+                Err(ApiError::Forbidden(format!("Token is missing the {} scope", scope)))
+            }
+        }
+    }
+
+    // Changing todos may require a verified email address, depending on the policy
+    pub fn require_verified_email(&self) -> Result<(), ApiError> {
+        if !self.email_verified && *EMAIL_VERIFICATION_POLICY == EmailVerificationPolicy::Write {
+            return Err(ApiError::Forbidden("Verify your email address first".to_string()));
+        }
+        Ok(())
+    }
+
+    // Account and token management is not available to personal access tokens
+    pub fn require_session(&self) -> Result<(), ApiError> {
+        match self.credential {
+            Credential::Session { .. } => Ok(()),
+            Credential::PersonalAccessToken { .. } => Err(ApiError::Forbidden(
+                "This endpoint requires an interactive login".to_string(),
+            )),
+        }
+    }
 }
 
 #[async_trait]
@@ -97,27 +426,15 @@ impl<S> FromRequestParts<S> for AuthenticatedUser
 where
     S: Send + Sync,
 {
-    type Rejection = StatusCode;
+    type Rejection = ApiError;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let auth_header = parts
-            .headers
-            .get(header::AUTHORIZATION)
-            .and_then(|header| header.to_str().ok())
-            .and_then(|value| {
-                if value.starts_with("Bearer ") {
-                    Some(value[7..].trim())
-                } else {
-                    None
-                }
-            })
-            .ok_or(StatusCode::UNAUTHORIZED)?;
-
-        let token_data = verify_jwt(auth_header)
-            .map_err(|_| StatusCode::UNAUTHORIZED)?;
-
-        Ok(AuthenticatedUser {
-            username: token_data.claims.sub,
-        })
+        let pool = parts
+            .extensions
+            .get::<Pool<Postgres>>()
+            .ok_or_else(|| ApiError::Internal("Database pool missing from request extensions".to_string()))?;
+        let token = session::request_token(&parts.method, &parts.headers)?;
+
+        authenticate(pool, &token).await
     }
 }
\ No newline at end of file
@@ -8,4 +8,24 @@ pub async fn connect_to_db(database_url: &str) -> Result<Pool<Postgres>> {
         .connect(database_url)
         .await?;
     Ok(pool)
-}
\ No newline at end of file
+}
+
+// The name of the unique constraint an error violated (Postgres error 23505), if any
+pub fn unique_violation(err: &sqlx::Error) -> Option<String> {
+    match err {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+            Some(db.constraint().unwrap_or_default().to_string())
+        }
+        _ => None,
+    }
+}
+
+// The name of the check constraint an error violated (Postgres error 23514), if any
+pub fn check_violation(err: &sqlx::Error) -> Option<String> {
+    match err {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23514") => {
+            Some(db.constraint().unwrap_or_default().to_string())
+        }
+        _ => None,
+    }
+}
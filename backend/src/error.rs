@@ -0,0 +1,199 @@
+use axum::{
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jsonwebtoken::errors::ErrorKind;
+use rand::Rng;
+use crate::db;
+use crate::models::ProblemDetails;
+use crate::password::PasswordError;
+use crate::validation::ValidationErrors;
+
+pub const TRACE_ID_HEADER: &str = "x-trace-id";
+
+tokio::task_local! {
+    // Id of the request being handled, echoed in error bodies and logs
+    static TRACE_ID: String;
+}
+
+// Every error a handler can return. Rendered as application/problem+json (RFC 9457)
+// with a stable `code`; details of internal errors are logged, never sent.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    UnsupportedMediaType(String),
+    Validation(ValidationErrors),
+    RateLimited { retry_after: i64 },
+    BadGateway(String),
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // Machine-readable error code; part of the API contract, do not rename
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::BadGateway(_) => "upstream_error",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    // Message that is safe to show to the client
+    fn detail(&self) -> String {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::UnsupportedMediaType(message) => message.clone(),
+            ApiError::Validation(_) => "Validation failed".to_string(),
+            ApiError::RateLimited { retry_after } => {
+                format!("Too many attempts. Try again in {} seconds", retry_after)
+            }
+            ApiError::BadGateway(_) => "The identity provider could not be reached".to_string(),
+            ApiError::Internal(_) => "An internal error occurred".to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let trace_id = current_trace_id();
+        if let ApiError::Internal(detail) | ApiError::BadGateway(detail) = &self {
+            eprintln!("[{}] {}", trace_id, detail);
+        }
+
+        let status = self.status();
+        let body = ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code().to_string(),
+            trace_id,
+            errors: match &self {
+                ApiError::Validation(errors) => Some(errors.fields()),
+                _ => None,
+            },
+        };
+
+        let mut response = (status, Json(body)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        if let ApiError::RateLimited { retry_after } = self {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => ApiError::NotFound("Resource not found".to_string()),
+            err if db::unique_violation(&err).is_some() => {
+                ApiError::Conflict("Resource already exists".to_string())
+            }
+            err => ApiError::Internal(format!("Database error: {}", err)),
+        }
+    }
+}
+
+impl From<bcrypt::BcryptError> for ApiError {
+    fn from(err: bcrypt::BcryptError) -> Self {
+        ApiError::Internal(format!("Password hashing error: {}", err))
+    }
+}
+
+impl From<PasswordError> for ApiError {
+    fn from(err: PasswordError) -> Self {
+        ApiError::Internal(format!("Password hashing error: {}", err))
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for ApiError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        match err.kind() {
+            // Problems with a token presented by the client
+            ErrorKind::InvalidToken
+            | ErrorKind::InvalidSignature
+            | ErrorKind::ExpiredSignature
+            | ErrorKind::ImmatureSignature
+            | ErrorKind::InvalidIssuer
+            | ErrorKind::InvalidAudience
+            | ErrorKind::InvalidSubject
+            | ErrorKind::InvalidAlgorithm
+            | ErrorKind::MissingAlgorithm
+            | ErrorKind::MissingRequiredClaim(_)
+            | ErrorKind::Base64(_)
+            | ErrorKind::Json(_)
+            | ErrorKind::Utf8(_) => ApiError::Unauthorized("Invalid or expired token".to_string()),
+            // Problems with our own keys
+            _ => ApiError::Internal(format!("Token error: {}", err)),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        ApiError::Internal(format!("{:#}", err))
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        ApiError::Validation(errors)
+    }
+}
+
+fn current_trace_id() -> String {
+    TRACE_ID
+        .try_with(String::clone)
+        .unwrap_or_else(|_| new_trace_id())
+}
+
+fn new_trace_id() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 16]>())
+}
+
+// Outermost middleware: gives every request a trace id, returned in the
+// X-Trace-Id header and in error bodies so reports can be matched to logs
+pub async fn trace_requests<B>(req: Request<B>, next: Next<B>) -> Response {
+    let trace_id = new_trace_id();
+    let mut response = TRACE_ID.scope(trace_id.clone(), next.run(req)).await;
+    if let Ok(value) = HeaderValue::from_str(&trace_id) {
+        response.headers_mut().insert(TRACE_ID_HEADER, value);
+    }
+    response
+}
@@ -0,0 +1,192 @@
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{rejection::JsonRejection, FromRequest, FromRequestParts},
+    http::{header, request::Parts, Request},
+    BoxError,
+};
+use serde::de::DeserializeOwned;
+use crate::error::ApiError;
+use crate::validation::ValidationErrors;
+
+// Stand-ins for axum's extractors that reject with `ApiError`, so malformed
+// bodies, paths and query strings get problem details like every other error.
+// Payloads with rules to check use `ValidatedJson` instead of `JsonBody`.
+
+pub(crate) fn json_rejection(rejection: JsonRejection) -> ApiError {
+    match rejection {
+        JsonRejection::MissingJsonContentType(_) => {
+            ApiError::UnsupportedMediaType("Expected a JSON body".to_string())
+        }
+        JsonRejection::JsonDataError(err) => {
+            let mut errors = ValidationErrors::default();
+            errors.add("body", err.body_text());
+            ApiError::Validation(errors)
+        }
+        rejection => ApiError::BadRequest(rejection.body_text()),
+    }
+}
+
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for JsonBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = axum::Json::<T>::from_request(req, state)
+            .await
+            .map_err(json_rejection)?;
+        Ok(JsonBody(value))
+    }
+}
+
+// A JSON body that may be left out entirely. A request without a body and
+// without a Content-Type gives `None`; anything else must be valid JSON, so a
+// malformed body is rejected rather than treated as missing.
+pub struct OptionalJson<T>(pub Option<T>);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for OptionalJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers();
+        let no_body = !headers.contains_key(header::CONTENT_TYPE)
+            && !headers.contains_key(header::TRANSFER_ENCODING)
+            && headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .unwrap_or("0")
+                == "0";
+        if no_body {
+            return Ok(OptionalJson(None));
+        }
+        let JsonBody(value) = JsonBody::<T>::from_request(req, state).await?;
+        Ok(OptionalJson(Some(value)))
+    }
+}
+
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Path(value) = axum::extract::Path::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+        Ok(Path(value))
+    }
+}
+
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(value) = axum::extract::Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+        Ok(Query(value))
+    }
+}
+
+// Like `Query`, but keys may repeat (tag=a&tag=b) to fill a `Vec`
+pub struct RepeatedQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for RepeatedQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum_extra::extract::Query(value) = axum_extra::extract::Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| ApiError::BadRequest(rejection.to_string()))?;
+        Ok(RepeatedQuery(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OptionalJson, Query};
+    use crate::error::ApiError;
+    use axum::{
+        body::Body,
+        extract::{FromRequest, FromRequestParts},
+        http::{header, Request},
+    };
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Deserialize)]
+    struct Payload {
+        refresh_token: String,
+    }
+
+    async fn optional_json(content_type: Option<&str>, body: &'static str) -> Result<Option<Payload>, ApiError> {
+        let mut request = Request::post("/token/refresh");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        if !body.is_empty() {
+            request = request.header(header::CONTENT_LENGTH, body.len());
+        }
+        let request = request.body(Body::from(body)).unwrap();
+        OptionalJson::<Payload>::from_request(request, &()).await.map(|OptionalJson(value)| value)
+    }
+
+    #[tokio::test]
+    async fn optional_json_is_only_absent_without_a_body() {
+        assert!(matches!(optional_json(None, "").await, Ok(None)));
+        assert!(matches!(
+            optional_json(Some("application/json"), r#"{"refresh_token":"x"}"#).await,
+            Ok(Some(payload)) if payload.refresh_token == "x"
+        ));
+        assert!(matches!(optional_json(Some("application/json"), "{").await, Err(ApiError::BadRequest(_))));
+        assert!(matches!(
+            optional_json(Some("application/json"), r#"{"token":"x"}"#).await,
+            Err(ApiError::Validation(_))
+        ));
+        assert!(matches!(
+            optional_json(None, r#"{"refresh_token":"x"}"#).await,
+            Err(ApiError::UnsupportedMediaType(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn malformed_query_strings_are_problems() {
+        let (mut parts, _) = Request::get("/todos?limit=-1").body(()).unwrap().into_parts();
+        let result = Query::<HashMap<String, usize>>::from_request_parts(&mut parts, &()).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+}
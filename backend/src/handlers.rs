@@ -1,21 +1,58 @@
 use axum::{
-    extract::{Extension, Path, Query},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{ConnectInfo, Extension},
+    http::{header, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
     Json,
 };
-use bcrypt::{hash, DEFAULT_COST};
 use sqlx::Pool;
 use sqlx::Postgres;
-use crate::models::{Todo, NewTodo, UpdateTodo, RegisterPayload, LoginPayload, TodoQueryParams, TokenResponse, User};
-use bcrypt::verify;
+use sqlx::QueryBuilder;
+use sqlx::Transaction;
+use sqlx::postgres::PgExecutor;
+use crate::models::{Todo, NewTodo, UpdateTodo, RegisterPayload, LoginPayload, TodoQueryParams, DueWindow, RenderFormat, RenderParams, Tag, TagMatch, Project, TodoInclude, MoveTodoPayload, OccurrenceParams, RecurrencePreview, TokenResponse, User, UserProfile, UpdateProfilePayload, RegisterResponse, RefreshPayload, RefreshToken, LogoutPayload, PersonalAccessToken, NewPersonalAccessToken, CreatedPersonalAccessToken,
+    LoginResponse, TwoFactorChallenge, TwoFactorLoginPayload, TwoFactorCodePayload, TotpEnrollment, RecoveryCodes, TotpState, Role,
+    ChangePasswordPayload, ForgotPasswordPayload, ResetPasswordPayload, JwkSet, CookieSession,
+    VerifyEmailPayload, ResendVerificationPayload, DeleteAccountPayload, AccountExport, LinkedIdentity};
+use chrono::{DateTime, Utc};
+use std::net::SocketAddr;
 use crate::auth;
-use crate::auth::AuthenticatedUser;
-use serde_json::json;
+use crate::error::ApiError;
+use crate::auth::{AuthenticatedUser, Credential};
+use crate::keys::KEYRING;
+use crate::password;
+use crate::throttle;
+use crate::totp;
+use crate::mailer::{Email, Mailer};
+use crate::session;
+use crate::sort;
+use crate::markdown;
+use crate::tags;
+use crate::projects;
+use crate::subtasks;
+use crate::rank;
+use crate::recurrence::{self, Rule};
+use crate::validation::{ValidatedJson, ValidationErrors};
+use crate::db;
+use crate::extract::{JsonBody, OptionalJson, Path, Query, RepeatedQuery};
+
+// Columns selected into `models::Todo`; queries alias todos as `t`
+pub const TODO_COLUMNS: &str = "t.id, t.title, t.completed, t.user_id, t.due_at, t.start_at, t.priority,
+     t.created_at, t.updated_at, t.completed_at, t.notes,
+     ARRAY(SELECT tg.name FROM todo_tags tt JOIN tags tg ON tg.id = tt.tag_id
+           WHERE tt.todo_id = t.id ORDER BY lower(tg.name)) AS tags,
+     t.project_id, t.parent_id, t.position, t.recurrence";
 
 /// Get all todos for the authenticated user
 /// 
-/// Filter todos by completed status and search by title
+/// Filter todos by completed status, title, due date and when they were
+/// created, last changed or completed. `due=today` and `due=this_week` use
+/// the time zone from the user's profile. `sort` takes a comma-separated
+/// list of keys such as `-priority,due_at`. With `render=html` the Markdown
+/// notes are also returned as sanitized HTML in `notes_html`. Repeat `tag`
+/// to filter by several tags, matching all of them unless `tag_match=any`.
+/// `project_id` limits the list to one project and `inbox=true` to todos
+/// without a project. `include=children` nests each todo's subtasks under
+/// it; combine it with `top_level=true` to get whole trees.
 #[utoipa::path(
     get,
     path = "/todos",
@@ -24,8 +61,9 @@ use serde_json::json;
     ),
     responses(
         (status = 200, description = "List of todos", body = [Todo]),
-        (status = 401, description = "Unauthorized"),
-        (status = 500, description = "Internal server error")
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Unknown sort key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
     ),
     security(
         ("bearerAuth" = [])
@@ -34,71 +72,120 @@ use serde_json::json;
 pub async fn get_all_todos_handler(
     Extension(pool): Extension<Pool<Postgres>>,
     Extension(auth_user): Extension<AuthenticatedUser>,
-    Query(params): Query<TodoQueryParams>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let todos = match (params.completed, &params.search) {
-        // Case 1: Both completed and search are provided
-        (Some(completed), Some(search)) => {
-            sqlx::query_as::<_, Todo>(
-                "SELECT t.id, t.title, t.completed, t.user_id 
-                 FROM todos t
-                 JOIN users u ON t.user_id = u.id
-                 WHERE u.username = $1 AND t.completed = $2 AND t.title ILIKE '%' || $3 || '%'"
-            )
-            .bind(&auth_user.username)
-            .bind(completed)
-            .bind(search)
-            .fetch_all(&pool)
-            .await
-        },
-        
-        // Case 2: Only completed filter is provided
-        (Some(completed), None) => {
-            sqlx::query_as::<_, Todo>(
-                "SELECT t.id, t.title, t.completed, t.user_id 
-                 FROM todos t
-                 JOIN users u ON t.user_id = u.id
-                 WHERE u.username = $1 AND t.completed = $2"
-            )
-            .bind(&auth_user.username)
-            .bind(completed)
-            .fetch_all(&pool)
-            .await
-        },
-        
-        // Case 3: Only search filter is provided
-        (None, Some(search)) => {
-            sqlx::query_as::<_, Todo>(
-                "SELECT t.id, t.title, t.completed, t.user_id 
-                 FROM todos t
-                 JOIN users u ON t.user_id = u.id
-                 WHERE u.username = $1 AND t.title ILIKE '%' || $2 || '%'"
-            )
-            .bind(&auth_user.username)
-            .bind(search)
-            .fetch_all(&pool)
-            .await
-        },
-        
-        // Case 4: No filters provided
-        (None, None) => {
-            sqlx::query_as::<_, Todo>(
-                "SELECT t.id, t.title, t.completed, t.user_id 
-                 FROM todos t
-                 JOIN users u ON t.user_id = u.id
-                 WHERE u.username = $1"
+    // So that `tag` may be repeated
+    RepeatedQuery(params): RepeatedQuery<TodoQueryParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_READ)?;
+    let order_by = sort::order_by(params.sort.as_deref())?;
+
+    let mut query = QueryBuilder::<Postgres>::new(format!(
+        "SELECT {} FROM todos t JOIN users u ON t.user_id = u.id WHERE u.username = ",
+        TODO_COLUMNS
+    ));
+    query.push_bind(&auth_user.username);
+
+    if let Some(completed) = params.completed {
+        query.push(" AND t.completed = ").push_bind(completed);
+    }
+    if let Some(search) = &params.search {
+        query
+            .push(" AND (t.title ILIKE '%' || ")
+            .push_bind(search)
+            .push(" || '%' OR t.notes ILIKE '%' || ")
+            .push_bind(search)
+            .push(" || '%')");
+    }
+    if let Some(due_before) = params.due_before {
+        query.push(" AND t.due_at < ").push_bind(due_before);
+    }
+    if let Some(due_after) = params.due_after {
+        query.push(" AND t.due_at > ").push_bind(due_after);
+    }
+    if let Some(created_before) = params.created_before {
+        query.push(" AND t.created_at < ").push_bind(created_before);
+    }
+    if let Some(created_after) = params.created_after {
+        query.push(" AND t.created_at > ").push_bind(created_after);
+    }
+    if let Some(updated_before) = params.updated_before {
+        query.push(" AND t.updated_at < ").push_bind(updated_before);
+    }
+    if let Some(updated_after) = params.updated_after {
+        query.push(" AND t.updated_at > ").push_bind(updated_after);
+    }
+    if let Some(completed_before) = params.completed_before {
+        query.push(" AND t.completed_at < ").push_bind(completed_before);
+    }
+    if let Some(completed_after) = params.completed_after {
+        query.push(" AND t.completed_at > ").push_bind(completed_after);
+    }
+    if let Some(project_id) = params.project_id {
+        query.push(" AND t.project_id = ").push_bind(project_id);
+    }
+    match params.inbox {
+        Some(true) => {
+            query.push(" AND t.project_id IS NULL");
+        }
+        Some(false) => {
+            query.push(" AND t.project_id IS NOT NULL");
+        }
+        None => {}
+    }
+    if let Some(parent_id) = params.parent_id {
+        query.push(" AND t.parent_id = ").push_bind(parent_id);
+    }
+    if let Some(top_level) = params.top_level {
+        query.push(if top_level { " AND t.parent_id IS NULL" } else { " AND t.parent_id IS NOT NULL" });
+    }
+    if !params.tag.is_empty() {
+        let names: Vec<String> = tags::normalize_names(&params.tag)
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        let required = match params.tag_match.unwrap_or(TagMatch::All) {
+            TagMatch::All => names.len() as i64,
+            TagMatch::Any => 1,
+        };
+        query
+            .push(
+                " AND (SELECT COUNT(*) FROM todo_tags tt JOIN tags tg ON tg.id = tt.tag_id
+                       WHERE tt.todo_id = t.id AND lower(tg.name) = ANY(",
             )
-            .bind(&auth_user.username)
-            .fetch_all(&pool)
-            .await
-        },
-    }
-    .map_err(|err| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("DB Error: {}", err),
-        )
-    })?;
+            .push_bind(names)
+            .push(")) >= ")
+            .push_bind(required);
+    }
+    match params.overdue {
+        Some(true) => {
+            query.push(" AND t.due_at < NOW() AND NOT t.completed");
+        }
+        Some(false) => {
+            query.push(" AND NOT COALESCE(t.due_at < NOW() AND NOT t.completed, FALSE)");
+        }
+        None => {}
+    }
+    if let Some(window) = params.due {
+        // Day and week boundaries are taken in the user's time zone.
+        let (unit, length) = match window {
+            DueWindow::Today => ("day", "1 day"),
+            DueWindow::ThisWeek => ("week", "7 days"),
+        };
+        query.push(format!(
+            " AND t.due_at >= date_trunc('{unit}', NOW() AT TIME ZONE u.timezone) AT TIME ZONE u.timezone
+              AND t.due_at < (date_trunc('{unit}', NOW() AT TIME ZONE u.timezone) + INTERVAL '{length}') AT TIME ZONE u.timezone"
+        ));
+    }
+    query.push(order_by);
+
+    let mut todos = query.build_query_as::<Todo>().fetch_all(&pool).await?;
+    if params.include == Some(TodoInclude::Children) {
+        let mut tx = pool.begin().await?;
+        subtasks::attach_children(&mut tx, &mut todos).await?;
+        tx.commit().await?;
+    }
+    if params.render == Some(RenderFormat::Html) {
+        todos.iter_mut().for_each(render_tree);
+    }
 
     Ok(Json(todos))
 }
@@ -110,8 +197,10 @@ pub async fn get_all_todos_handler(
     request_body = NewTodo,
     responses(
         (status = 200, description = "Todo created successfully", body = Todo),
-        (status = 401, description = "Unauthorized"),
-        (status = 500, description = "Internal server error")
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid title, schedule, tags, project, parent or recurrence", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
     ),
     security(
         ("bearerAuth" = [])
@@ -120,38 +209,57 @@ pub async fn get_all_todos_handler(
 pub async fn create_todo_handler(
     Extension(pool): Extension<Pool<Postgres>>,
     Extension(auth_user): Extension<AuthenticatedUser>,
-    Json(payload): Json<NewTodo>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+    ValidatedJson(payload): ValidatedJson<NewTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
     // First get the user_id for the authenticated user
     let user_id = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = $1")
         .bind(&auth_user.username)
         .fetch_one(&pool)
-        .await
-        .map_err(|err| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("User lookup error: {}", err),
-            )
-        })?;
+        .await?;
 
-    // Now create the todo associated with this user
-    let inserted_todo = sqlx::query_as::<_, Todo>(
-        "INSERT INTO todos (title, completed, user_id) 
-         VALUES ($1, $2, $3) 
-         RETURNING id, title, completed, user_id"
-    )
-    .bind(&payload.title)
+    // Now create the todo associated with this user, together with its tags
+    let mut tx = pool.begin().await?;
+    if let Some(project_id) = payload.project_id {
+        projects::check_owned(&mut tx, &auth_user.username, project_id).await?;
+    }
+    if let Some(parent_id) = payload.parent_id {
+        subtasks::check_parent(&mut tx, &auth_user.username, None, parent_id).await?;
+    }
+    // New todos go to the end of their list
+    let position = rank::append_position(&mut tx, user_id, payload.project_id, payload.parent_id).await?;
+    let mut inserted_todo = sqlx::query_as::<_, Todo>(&format!(
+        "INSERT INTO todos AS t (title, completed, user_id, due_at, start_at, priority, notes, project_id, parent_id, position, completed_at,
+                                 recurrence, recurrence_start)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, CASE WHEN $2 THEN NOW() END,
+                 $11, CASE WHEN $11::text IS NOT NULL THEN $4 END)
+         RETURNING {}",
+        TODO_COLUMNS
+    ))
+    .bind(payload.title.trim())
     .bind(payload.completed.unwrap_or(false))
     .bind(user_id)
-    .fetch_one(&pool)
-    .await
-    .map_err(|err| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("DB Error: {}", err),
-        )
-    })?;
-    
+    .bind(payload.due_at)
+    .bind(payload.start_at)
+    .bind(payload.priority.as_deref().unwrap_or("none"))
+    .bind(payload.notes)
+    .bind(payload.project_id)
+    .bind(payload.parent_id)
+    .bind(position)
+    .bind(payload.recurrence.as_deref().and_then(canonical_rule))
+    .fetch_one(&mut tx)
+    .await?;
+
+    if !payload.tags.is_empty() {
+        inserted_todo.tags = tags::set_todo_tags(&mut tx, user_id, inserted_todo.id, &payload.tags).await?;
+    }
+    if payload.parent_id.is_some() {
+        subtasks::apply_completion_rules(&mut tx, user_id, inserted_todo.id, None).await?;
+    }
+    tx.commit().await?;
+
     Ok(Json(inserted_todo))
 }
 
@@ -160,13 +268,14 @@ pub async fn create_todo_handler(
     get,
     path = "/todos/{id}",
     params(
-        ("id" = i32, Path, description = "Todo ID")
+        ("id" = i32, Path, description = "Todo ID"),
+        RenderParams
     ),
     responses(
         (status = 200, description = "Todo found", body = Todo),
-        (status = 401, description = "Unauthorized"),
-        (status = 404, description = "Todo not found"),
-        (status = 500, description = "Internal server error")
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Todo not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
     ),
     security(
         ("bearerAuth" = [])
@@ -176,33 +285,103 @@ pub async fn get_todo_handler(
     Extension(pool): Extension<Pool<Postgres>>,
     Extension(auth_user): Extension<AuthenticatedUser>,
     Path(id): Path<i32>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+    Query(params): Query<RenderParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_READ)?;
+
     // Only get the todo if it belongs to the authenticated user
-    let todo = sqlx::query_as::<_, Todo>(
-        "SELECT t.id, t.title, t.completed, t.user_id 
+    let todo = sqlx::query_as::<_, Todo>(&format!(
+        "SELECT {}
          FROM todos t
          JOIN users u ON t.user_id = u.id
-         WHERE t.id = $1 AND u.username = $2"
-    )
+         WHERE t.id = $1 AND u.username = $2",
+        TODO_COLUMNS
+    ))
     .bind(id)
     .bind(&auth_user.username)
     .fetch_optional(&pool)
-    .await
-    .map_err(|err| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Database error: {}", err),
-        )
-    })?;
+    .await?;
 
-    if let Some(todo) = todo {
+    if let Some(mut todo) = todo {
+        if params.render == Some(RenderFormat::Html) {
+            markdown::render_notes(&mut todo);
+        }
         Ok(Json(todo))
     } else {
-        Err((StatusCode::NOT_FOUND, format!("Todo with id {} not found", id)))
+        Err(ApiError::NotFound(format!("Todo with id {} not found", id)))
+    }
+}
+
+// Render the notes of a todo and all of its nested subtasks
+fn render_tree(todo: &mut Todo) {
+    markdown::render_notes(todo);
+    for child in todo.children.iter_mut().flatten() {
+        render_tree(child);
+    }
+}
+
+/// Get a todo together with all of its subtasks
+///
+/// Subtasks are nested under `children` at every level.
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/tree",
+    params(
+        ("id" = i32, Path, description = "Todo ID"),
+        RenderParams
+    ),
+    responses(
+        (status = 200, description = "Todo with its subtree", body = Todo),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Todo not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn get_todo_tree_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+    Query(params): Query<RenderParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_READ)?;
+
+    // Read the root and its subtree from one snapshot
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut tx)
+        .await?;
+
+    let todo = sqlx::query_as::<_, Todo>(&format!(
+        "SELECT {}
+         FROM todos t
+         JOIN users u ON t.user_id = u.id
+         WHERE t.id = $1 AND u.username = $2",
+        TODO_COLUMNS
+    ))
+    .bind(id)
+    .bind(&auth_user.username)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Todo with id {} not found", id)))?;
+
+    let mut roots = [todo];
+    subtasks::attach_children(&mut tx, &mut roots).await?;
+    tx.commit().await?;
+
+    let [mut todo] = roots;
+    if params.render == Some(RenderFormat::Html) {
+        render_tree(&mut todo);
     }
+    Ok(Json(todo))
 }
 
 /// Update a todo
+///
+/// Completing a recurring todo creates its next occurrence as a new open
+/// todo and stops the completed one from repeating.
 #[utoipa::path(
     put,
     path = "/todos/{id}",
@@ -212,9 +391,11 @@ pub async fn get_todo_handler(
     request_body = UpdateTodo,
     responses(
         (status = 200, description = "Todo updated successfully", body = Todo),
-        (status = 401, description = "Unauthorized"),
-        (status = 404, description = "Todo not found or not owned by you"),
-        (status = 500, description = "Internal server error")
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Todo not found or not owned by you", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid title, schedule, tags, project, parent or recurrence", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
     ),
     security(
         ("bearerAuth" = [])
@@ -224,37 +405,375 @@ pub async fn update_todo_handler(
     Extension(pool): Extension<Pool<Postgres>>,
     Extension(auth_user): Extension<AuthenticatedUser>,
     Path(id): Path<i32>,
-    Json(payload): Json<UpdateTodo>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    // Update the todo only if it belongs to the authenticated user
-    let updated_todo = sqlx::query_as::<_, Todo>(
+    ValidatedJson(payload): ValidatedJson<UpdateTodo>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
+    // Update the todo only if it belongs to the authenticated user.
+    // completed_at only moves when `completed` actually flips.
+    let mut tx = pool.begin().await?;
+    if let Some(Some(project_id)) = payload.project_id {
+        projects::check_owned(&mut tx, &auth_user.username, project_id).await?;
+    }
+    if let Some(Some(parent_id)) = payload.parent_id {
+        subtasks::check_parent(&mut tx, &auth_user.username, Some(id), parent_id).await?;
+    }
+    // The state before this update, for the completion rules, the position
+    // and the next occurrence of a recurring todo
+    let (old_completed, old_parent_id, old_project_id) = sqlx::query_as::<_, (bool, Option<i32>, Option<i32>)>(
+        "SELECT t.completed, t.parent_id, t.project_id FROM todos t
+         JOIN users u ON t.user_id = u.id
+         WHERE t.id = $1 AND u.username = $2
+         FOR UPDATE OF t"
+    )
+    .bind(id)
+    .bind(&auth_user.username)
+    .fetch_optional(&mut tx)
+    .await?
+    .unwrap_or_default();
+    let updated_todo = sqlx::query_as::<_, Todo>(&format!(
         "UPDATE todos t
          SET title = COALESCE($1, t.title),
-             completed = COALESCE($2, t.completed)
+             completed = COALESCE($2, t.completed),
+             completed_at = CASE
+                 WHEN $2 IS NULL OR $2 = t.completed THEN t.completed_at
+                 WHEN $2 THEN NOW()
+                 ELSE NULL
+             END,
+             updated_at = NOW(),
+             due_at = CASE WHEN $5 THEN $6 ELSE t.due_at END,
+             start_at = CASE WHEN $7 THEN $8 ELSE t.start_at END,
+             priority = COALESCE($9, t.priority),
+             notes = CASE WHEN $10 THEN $11 ELSE t.notes END,
+             project_id = CASE WHEN $12 THEN $13 ELSE t.project_id END,
+             parent_id = CASE WHEN $14 THEN $15 ELSE t.parent_id END,
+             recurrence = CASE WHEN $16 THEN $17 ELSE t.recurrence END,
+             recurrence_start = CASE
+                 WHEN NOT $16 THEN t.recurrence_start
+                 WHEN $17::text IS NULL THEN NULL
+                 WHEN $5 THEN $6
+                 ELSE t.due_at
+             END
          FROM users u
          WHERE t.id = $3 
          AND t.user_id = u.id
          AND u.username = $4
-         RETURNING t.id, t.title, t.completed, t.user_id"
-    )
-    .bind(payload.title)
+         RETURNING {}",
+        TODO_COLUMNS
+    ))
+    .bind(payload.title.as_deref().map(str::trim))
     .bind(payload.completed)
     .bind(id)
     .bind(&auth_user.username)
-    .fetch_optional(&pool)
+    .bind(payload.due_at.is_some())
+    .bind(payload.due_at.flatten())
+    .bind(payload.start_at.is_some())
+    .bind(payload.start_at.flatten())
+    .bind(payload.priority.as_deref())
+    .bind(payload.notes.is_some())
+    .bind(payload.notes.flatten())
+    .bind(payload.project_id.is_some())
+    .bind(payload.project_id.flatten())
+    .bind(payload.parent_id.is_some())
+    .bind(payload.parent_id.flatten())
+    .bind(payload.recurrence.is_some())
+    .bind(payload.recurrence.as_ref().and_then(|rule| rule.as_deref().and_then(canonical_rule)))
+    .fetch_optional(&mut tx)
     .await
-    .map_err(|err| (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        format!("DB Error: {}", err),
-    ))?;
+    .map_err(|err| match db::check_violation(&err).as_deref() {
+        Some("todos_start_before_due") => {
+            let mut errors = ValidationErrors::default();
+            errors.add("start_at", "must not be after due_at");
+            ApiError::Validation(errors)
+        }
+        Some("todos_recurrence_needs_due") => {
+            let mut errors = ValidationErrors::default();
+            errors.add("due_at", "is required for recurring todos");
+            ApiError::Validation(errors)
+        }
+        _ => err.into(),
+    })?;
+
+    let mut todo = updated_todo
+        .ok_or_else(|| ApiError::NotFound(format!("Todo with id {} not found or not owned by you", id)))?;
+    if let Some(names) = &payload.tags {
+        todo.tags = tags::set_todo_tags(&mut tx, todo.user_id, todo.id, names).await?;
+    }
+    if todo.completed && !old_completed && todo.recurrence.is_some() {
+        create_next_occurrence(&mut tx, &mut todo).await?;
+    }
+    if payload.completed.is_some() || payload.parent_id.is_some() {
+        subtasks::apply_completion_rules(&mut tx, todo.user_id, todo.id, old_parent_id).await?;
+    }
+    // A todo moved to another project or parent goes to the end of that list
+    if (todo.project_id, todo.parent_id) != (old_project_id, old_parent_id) {
+        let position = rank::append_position(&mut tx, todo.user_id, todo.project_id, todo.parent_id).await?;
+        sqlx::query("UPDATE todos SET position = $1 WHERE id = $2")
+            .bind(&position)
+            .bind(todo.id)
+            .execute(&mut tx)
+            .await?;
+        todo.position = position;
+    }
+    tx.commit().await?;
+
+    Ok(Json(todo))
+}
+
+// Rules are stored in canonical form so equal rules compare equal
+fn canonical_rule(rule: &str) -> Option<String> {
+    rule.parse::<Rule>().ok().map(|rule| rule.to_string())
+}
+
+// Create the open todo for the next occurrence of a recurring todo that was
+// just completed, and stop the completed one from repeating. Once the rule
+// has no occurrences left (COUNT or UNTIL reached) no new todo is created.
+async fn create_next_occurrence(tx: &mut Transaction<'_, Postgres>, todo: &mut Todo) -> Result<(), ApiError> {
+    let (recurrence_start, timezone) = sqlx::query_as::<_, (Option<DateTime<Utc>>, String)>(
+        "SELECT t.recurrence_start, u.timezone FROM todos t
+         JOIN users u ON t.user_id = u.id
+         WHERE t.id = $1"
+    )
+    .bind(todo.id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let rule = todo.recurrence.as_deref().and_then(|rule| rule.parse::<Rule>().ok());
+    let next_due = match (rule, todo.due_at) {
+        (Some(rule), Some(due_at)) => {
+            let start = recurrence_start.unwrap_or(due_at);
+            recurrence::occurrences_after(&rule, start, recurrence::time_zone(&timezone), due_at, 1)
+                .first()
+                .map(|next_due| (due_at, *next_due))
+        }
+        _ => None,
+    };
+
+    if let Some((due_at, next_due)) = next_due {
+        // The start date keeps the same distance to the due date
+        let next_start = todo.start_at.map(|start_at| start_at + (next_due - due_at));
+        let position = rank::append_position(tx, todo.user_id, todo.project_id, todo.parent_id).await?;
+        let next_id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO todos (title, completed, user_id, due_at, start_at, priority, notes, project_id, parent_id, position,
+                                recurrence, recurrence_start)
+             VALUES ($1, FALSE, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+             RETURNING id"
+        )
+        .bind(&todo.title)
+        .bind(todo.user_id)
+        .bind(next_due)
+        .bind(next_start)
+        .bind(&todo.priority)
+        .bind(&todo.notes)
+        .bind(todo.project_id)
+        .bind(todo.parent_id)
+        .bind(position)
+        .bind(&todo.recurrence)
+        .bind(recurrence_start.unwrap_or(due_at))
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query("INSERT INTO todo_tags (todo_id, tag_id) SELECT $1, tag_id FROM todo_tags WHERE todo_id = $2")
+            .bind(next_id)
+            .bind(todo.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::query("UPDATE todos SET recurrence = NULL, recurrence_start = NULL WHERE id = $1")
+        .bind(todo.id)
+        .execute(&mut *tx)
+        .await?;
+    todo.recurrence = None;
+    Ok(())
+}
+
+/// Preview the upcoming occurrences of a recurring todo
+///
+/// Returns the due dates that completing the todo, and each occurrence after
+/// it, would create. Dates follow the time zone from the user's profile.
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/occurrences",
+    params(
+        ("id" = i32, Path, description = "Todo ID"),
+        OccurrenceParams
+    ),
+    responses(
+        (status = 200, description = "Upcoming occurrences", body = RecurrencePreview),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Todo not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid count, or the todo does not repeat", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn todo_occurrences_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+    Query(params): Query<OccurrenceParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_READ)?;
 
-    match updated_todo {
-        Some(todo) => Ok(Json(todo)),
-        None => Err((StatusCode::NOT_FOUND, format!("Todo with id {} not found or not owned by you", id)))
+    let count = params.count.unwrap_or(5);
+    if !(1..=100).contains(&count) {
+        let mut errors = ValidationErrors::default();
+        errors.add("count", "must be between 1 and 100");
+        return Err(ApiError::Validation(errors));
     }
+
+    let (recurrence, recurrence_start, due_at, timezone) =
+        sqlx::query_as::<_, (Option<String>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, String)>(
+            "SELECT t.recurrence, t.recurrence_start, t.due_at, u.timezone
+             FROM todos t
+             JOIN users u ON t.user_id = u.id
+             WHERE t.id = $1 AND u.username = $2"
+        )
+        .bind(id)
+        .bind(&auth_user.username)
+        .fetch_optional(&pool)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Todo with id {} not found", id)))?;
+
+    let (Some(recurrence), Some(due_at)) = (recurrence, due_at) else {
+        let mut errors = ValidationErrors::default();
+        errors.add("recurrence", "todo does not repeat");
+        return Err(ApiError::Validation(errors));
+    };
+    let rule = recurrence.parse::<Rule>().map_err(|message| {
+        let mut errors = ValidationErrors::default();
+        errors.add("recurrence", message);
+        ApiError::Validation(errors)
+    })?;
+    let occurrences = recurrence::occurrences_after(
+        &rule,
+        recurrence_start.unwrap_or(due_at),
+        recurrence::time_zone(&timezone),
+        due_at,
+        count,
+    );
+
+    Ok(Json(RecurrencePreview { recurrence, occurrences }))
+}
+
+/// Move a todo within its list
+///
+/// Places the todo right before `before` and/or right after `after`. Only the
+/// moved todo gets a new position; its neighbours are left untouched.
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/move",
+    params(
+        ("id" = i32, Path, description = "Todo ID")
+    ),
+    request_body = MoveTodoPayload,
+    responses(
+        (status = 200, description = "Todo moved", body = Todo),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Todo not found or not owned by you", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Missing anchor, or an anchor outside the todo's list", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn move_todo_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<MoveTodoPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
+    let mut tx = pool.begin().await?;
+
+    let (user_id, project_id, parent_id) = sqlx::query_as::<_, (i32, Option<i32>, Option<i32>)>(
+        "SELECT t.user_id, t.project_id, t.parent_id FROM todos t
+         JOIN users u ON t.user_id = u.id
+         WHERE t.id = $1 AND u.username = $2"
+    )
+    .bind(id)
+    .bind(&auth_user.username)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Todo with id {} not found or not owned by you", id)))?;
+
+    rank::lock_positions(&mut tx, user_id).await?;
+
+    // Positions of the other todos in the same list, in order
+    let siblings = sqlx::query_as::<_, (i32, String)>(
+        "SELECT id, position FROM todos
+         WHERE user_id = $1
+         AND project_id IS NOT DISTINCT FROM $2
+         AND parent_id IS NOT DISTINCT FROM $3
+         AND id <> $4
+         ORDER BY position, id"
+    )
+    .bind(user_id)
+    .bind(project_id)
+    .bind(parent_id)
+    .bind(id)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let index_of = |field: &'static str, anchor: i32| {
+        siblings.iter().position(|(sibling, _)| *sibling == anchor).ok_or_else(|| {
+            let mut errors = ValidationErrors::default();
+            errors.add(field, format!("todo {} is not in the same list", anchor));
+            ApiError::Validation(errors)
+        })
+    };
+    // The gap to move into, as indexes of the todos on either side of it
+    let (lower, upper) = match (payload.after, payload.before) {
+        (Some(after), Some(before)) => {
+            let (lower, upper) = (index_of("after", after)?, index_of("before", before)?);
+            if lower + 1 != upper {
+                let mut errors = ValidationErrors::default();
+                errors.add("before", "must directly follow after");
+                return Err(ApiError::Validation(errors));
+            }
+            (Some(lower), Some(upper))
+        }
+        (Some(after), None) => {
+            let lower = index_of("after", after)?;
+            (Some(lower), Some(lower + 1).filter(|&upper| upper < siblings.len()))
+        }
+        (None, Some(before)) => {
+            let upper = index_of("before", before)?;
+            (upper.checked_sub(1), Some(upper))
+        }
+        (None, None) => unreachable!("rejected by validation"),
+    };
+    let position = rank::between(
+        lower.map(|i| siblings[i].1.as_str()),
+        upper.map(|i| siblings[i].1.as_str()),
+    );
+
+    let todo = sqlx::query_as::<_, Todo>(&format!(
+        "UPDATE todos t SET position = $1, updated_at = NOW()
+         WHERE t.id = $2
+         RETURNING {}",
+        TODO_COLUMNS
+    ))
+    .bind(position)
+    .bind(id)
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(todo))
 }
 
 /// Delete a todo
+///
+/// Its subtasks are deleted with it.
 #[utoipa::path(
     delete,
     path = "/todos/{id}",
@@ -263,9 +782,10 @@ pub async fn update_todo_handler(
     ),
     responses(
         (status = 204, description = "Todo deleted successfully"),
-        (status = 401, description = "Unauthorized"),
-        (status = 404, description = "Todo not found or not owned by you"),
-        (status = 500, description = "Internal server error")
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Todo not found or not owned by you", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
     ),
     security(
         ("bearerAuth" = [])
@@ -275,124 +795,1416 @@ pub async fn delete_todo_handler(
     Extension(pool): Extension<Pool<Postgres>>,
     Extension(auth_user): Extension<AuthenticatedUser>,
     Path(id): Path<i32>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
     // Delete the todo only if it belongs to the authenticated user
-    let result = sqlx::query(
+    let mut tx = pool.begin().await?;
+    let deleted = sqlx::query_as::<_, (i32, Option<i32>)>(
         "DELETE FROM todos t
          USING users u
          WHERE t.id = $1 
          AND t.user_id = u.id
-         AND u.username = $2"
+         AND u.username = $2
+         RETURNING t.user_id, t.parent_id"
     )
     .bind(id)
     .bind(&auth_user.username)
-    .execute(&pool)
-    .await
-    .map_err(|err| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("DB Error: {}", err),
-        )
-    })?;
+    .fetch_optional(&mut tx)
+    .await?;
 
-    if result.rows_affected() == 0 {
-        Err((StatusCode::NOT_FOUND, format!("Todo with id {} not found or not owned by you", id)))
-    } else {
-        Ok(StatusCode::NO_CONTENT)
+    match deleted {
+        Some((user_id, parent_id)) => {
+            // The parent may now have only completed subtasks left
+            subtasks::parent_changed(&mut tx, user_id, parent_id).await?;
+            tx.commit().await?;
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err(ApiError::NotFound(format!("Todo with id {} not found or not owned by you", id))),
+    }
+}
+
+// A taken username or email address is a 409 that says which one clashed
+pub(crate) fn duplicate_user(err: sqlx::Error) -> ApiError {
+    match db::unique_violation(&err).as_deref() {
+        Some("users_email_key") => ApiError::Conflict("Email address is already registered".to_string()),
+        Some(_) => ApiError::Conflict("Username is already taken".to_string()),
+        None => err.into(),
     }
 }
 
 /// Register a new user
+///
+/// If an email address is given, a verification link is mailed to it. With
+/// `login`, tokens are returned too unless the account may not log in yet.
 #[utoipa::path(
     post,
     path = "/register",
     request_body = RegisterPayload,
     responses(
-        (status = 200, description = "User registered successfully", body = User),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "User registered successfully", body = RegisterResponse),
+        (status = 409, description = "Username or email address already taken", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid username, password or email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
     )
 )]
 pub async fn register_handler(
     Extension(pool): Extension<Pool<Postgres>>,
-    Json(payload): Json<RegisterPayload>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    // Hash the password using bcrypt.
-    let hashed_password = hash(payload.password, DEFAULT_COST).map_err(|err| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Password hashing error: {}", err),
-        )
-    })?;
-    
-    // Insert the user into the database, returning the new user.
+    Extension(mailer): Extension<Mailer>,
+    ValidatedJson(payload): ValidatedJson<RegisterPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    let email = payload
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|email| !email.is_empty())
+        .map(str::to_string);
+
+    // Hash the password using the configured algorithm.
+    let hashed_password = password::hash_password(&payload.password)?;
+
+    // Insert the user and its verification token together, returning the new user.
+    let mut tx = pool.begin().await?;
     let user = sqlx::query_as::<_, User>(
-        "INSERT INTO users (username, password) VALUES ($1, $2)
-         RETURNING id, username, password"
+        "INSERT INTO users (username, password, email) VALUES ($1, $2, $3)
+         RETURNING id, username, password, email, email_verified_at, role, totp_enabled, timezone,
+                   complete_children_with_parent, auto_complete_parents"
     )
     .bind(payload.username)
     .bind(hashed_password)
-    .fetch_one(&pool)
+    .bind(&email)
+    .fetch_one(&mut tx)
     .await
-    .map_err(|err| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("DB Error: {}", err),
+    .map_err(duplicate_user)?;
+
+    let verification = match &email {
+        Some(email) => Some((
+            email,
+            create_email_verification(&mut tx, user.id, email).await?,
+        )),
+        None => None,
+    };
+    tx.commit().await?;
+
+    // Mail only once the account exists. A failed delivery does not undo the
+    // registration; the link can be requested again at /email/verify/resend.
+    if let Some((email, token)) = verification {
+        if let Err(err) = mail_email_verification(&mailer, email, &token).await {
+            eprintln!("Could not send the verification email for user {}: {}", user.id, err);
+        }
+    }
+
+    // The account exists either way; a policy that blocks login (such as an
+    // unverified email address) only means no tokens yet.
+    let tokens = if payload.login {
+        match issue_tokens(&pool, user.id, None).await {
+            Ok(tokens) => Some(tokens),
+            Err(ApiError::Forbidden(_)) => None,
+            Err(err) => return Err(err),
+        }
+    } else {
+        None
+    };
+
+    Ok(Json(RegisterResponse {
+        user: user.into(),
+        tokens,
+    }))
+}
+
+/// Get the profile of the current user
+#[utoipa::path(
+    get,
+    path = "/me",
+    responses(
+        (status = 200, description = "Current user", body = UserProfile),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn me_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    let profile = sqlx::query_as::<_, UserProfile>(
+        "SELECT id, username, email, email_verified_at, role, totp_enabled, timezone,
+                complete_children_with_parent, auto_complete_parents
+         FROM users WHERE username = $1"
+    )
+    .bind(&auth_user.username)
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(profile))
+}
+
+/// Update the profile settings of the current user
+///
+/// `timezone` must be an IANA zone name known to the database, such as
+/// `Europe/Berlin`.
+#[utoipa::path(
+    put,
+    path = "/me",
+    request_body = UpdateProfilePayload,
+    responses(
+        (status = 200, description = "Profile updated", body = UserProfile),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Unknown time zone", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn update_profile_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    ValidatedJson(payload): ValidatedJson<UpdateProfilePayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(timezone) = &payload.timezone {
+        let known = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM pg_timezone_names WHERE name = $1)"
         )
-    })?;
-    
-    Ok(Json(user))
+        .bind(timezone)
+        .fetch_one(&pool)
+        .await?;
+        if !known {
+            let mut errors = ValidationErrors::default();
+            errors.add("timezone", "must be a known IANA time zone");
+            return Err(ApiError::Validation(errors));
+        }
+    }
+
+    let profile = sqlx::query_as::<_, UserProfile>(
+        "UPDATE users
+         SET timezone = COALESCE($1, timezone),
+             complete_children_with_parent = COALESCE($3, complete_children_with_parent),
+             auto_complete_parents = COALESCE($4, auto_complete_parents)
+         WHERE username = $2
+         RETURNING id, username, email, email_verified_at, role, totp_enabled, timezone,
+                   complete_children_with_parent, auto_complete_parents"
+    )
+    .bind(payload.timezone.as_deref())
+    .bind(&auth_user.username)
+    .bind(payload.complete_children_with_parent)
+    .bind(payload.auto_complete_parents)
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(profile))
 }
 
 /// Login a user and get authentication token
+///
+/// If the account has two-factor authentication enabled, a short-lived
+/// challenge token is returned instead, to be exchanged at `/login/2fa`.
 #[utoipa::path(
     post,
     path = "/login",
     request_body = LoginPayload,
     responses(
-        (status = 200, description = "Login successful", body = TokenResponse),
-        (status = 401, description = "Invalid credentials"),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Login successful, or a 2FA challenge", body = LoginResponse),
+        (status = 401, description = "Invalid username or password", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Account disabled, password reset required or email address not verified", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many failed attempts", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
     )
 )]
 pub async fn login_handler(
     Extension(pool): Extension<Pool<Postgres>>,
-    Json(payload): Json<LoginPayload>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    JsonBody(payload): JsonBody<LoginPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Refuse early while the account or the client address is locked out.
+    let account_key = throttle::account_key(&payload.username);
+    let ip_key = throttle::ip_key(&throttle::client_ip(&headers, &addr));
+    if let Some(retry_after) = throttle::locked_for(&pool, &[account_key.clone(), ip_key.clone()])
+        .await?
+    {
+        return Err(ApiError::RateLimited { retry_after });
+    }
+
     // Retrieve the user by username.
     let user = sqlx::query_as::<_, User>(
-        "SELECT id, username, password FROM users WHERE username = $1"
+        "SELECT id, username, password, email, email_verified_at, role, totp_enabled, timezone,
+                complete_children_with_parent, auto_complete_parents
+         FROM users WHERE username = $1"
     )
     .bind(&payload.username)
     .fetch_optional(&pool)
-    .await
-    .map_err(|err| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("DB error: {}", err),
-        )
-    })?;
-    
-    if let Some(user) = user {
-        // Verify the user's password against the stored hashed password.
-        let is_valid = verify(&payload.password, &user.password).map_err(|err| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Password verification error: {}", err),
-            )
-        })?;
-        if is_valid {
-            let token = auth::create_jwt(&user.username).map_err(|err| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Token creation error: {}", err),
-                )
-            })?;
-            // Return the token as JSON.
-            Ok(Json(json!({ "token": token })))
-        } else {
-            Err((StatusCode::UNAUTHORIZED, "Invalid credentials".to_string()))
+    .await?;
+
+    // Verify the user's password against the stored hashed password. Unknown users
+    // still pay for a verification so timing does not reveal which usernames exist.
+    let is_valid = match &user {
+        Some(user) => password::verify_password(&payload.password, &user.password)?,
+        None => {
+            password::verify_dummy(&payload.password);
+            false
         }
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "User not found".to_string()))
+    };
+
+    let user = match user {
+        Some(user) if is_valid => user,
+        _ => {
+            throttle::record_failure(&pool, &account_key, &throttle::ACCOUNT_POLICY).await?;
+            throttle::record_failure(&pool, &ip_key, &throttle::IP_POLICY).await?;
+            return Err(ApiError::Unauthorized("Invalid username or password".to_string()));
+        }
+    };
+
+    // Upgrade hashes made with an outdated algorithm or cost while we have the plaintext.
+    if password::needs_rehash(&user.password) {
+        let rehashed = password::hash_password(&payload.password)?;
+        sqlx::query("UPDATE users SET password = $1 WHERE id = $2")
+            .bind(rehashed)
+            .bind(user.id)
+            .execute(&pool)
+            .await?;
+    }
+
+    // The failure count is only cleared once the second factor has been checked too.
+    if user.totp_enabled {
+        let challenge_token = auth::create_challenge_token(&user.username)?;
+        return Ok(Json(LoginResponse::TwoFactorRequired(TwoFactorChallenge {
+            two_factor_required: true,
+            challenge_token,
+        }))
+        .into_response());
+    }
+
+    throttle::reset(&pool, &account_key).await?;
+
+    // Start a new refresh token family for this login.
+    let tokens = issue_tokens(&pool, user.id, None).await?;
+    Ok(token_response(tokens, payload.use_cookies))
+}
+
+// Mint an access token and a refresh token for a user who has fully authenticated.
+// Disabled accounts, accounts waiting for a forced password reset and, if the
+// policy says so, accounts with an unverified email address get neither.
+pub(crate) async fn issue_tokens(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    family_id: Option<&str>,
+) -> Result<TokenResponse, ApiError> {
+    let (username, token_generation, role, disabled, password_reset_required, email_unverified) =
+        sqlx::query_as::<_, (String, i32, String, bool, bool, bool)>(
+            "SELECT username, token_generation, role, disabled, password_reset_required,
+                    email IS NOT NULL AND email_verified_at IS NULL
+             FROM users WHERE id = $1"
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+    if disabled {
+        return Err(ApiError::Forbidden("Account disabled".to_string()));
     }
-}
\ No newline at end of file
+    if password_reset_required {
+        return Err(ApiError::Forbidden("Password reset required".to_string()));
+    }
+    if email_unverified && *auth::EMAIL_VERIFICATION_POLICY == auth::EmailVerificationPolicy::Login {
+        return Err(ApiError::Forbidden("Email address not verified".to_string()));
+    }
+
+    let token = auth::create_jwt(&username, token_generation, Role::from_db(&role))?;
+    let refresh_token = auth::issue_refresh_token(pool, user_id, family_id)
+        .await?;
+
+    Ok(TokenResponse { token, refresh_token })
+}
+
+// Hand a new session to the client, either in the body or as cookies
+fn token_response(tokens: TokenResponse, use_cookies: bool) -> Response {
+    if use_cookies {
+        let (cookies, csrf_token) = session::session_cookies(&tokens);
+        (AppendHeaders(cookies), Json(LoginResponse::Session(CookieSession { csrf_token }))).into_response()
+    } else {
+        Json(LoginResponse::Tokens(tokens)).into_response()
+    }
+}
+
+/// Complete a login with a two-factor code
+///
+/// Accepts either a current TOTP code or an unused recovery code.
+#[utoipa::path(
+    post,
+    path = "/login/2fa",
+    request_body = TwoFactorLoginPayload,
+    responses(
+        (status = 200, description = "Login successful", body = LoginResponse),
+        (status = 401, description = "Invalid or expired challenge, or invalid code", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Account disabled, password reset required or email address not verified", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many failed attempts", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn login_two_factor_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    JsonBody(payload): JsonBody<TwoFactorLoginPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    let username = auth::verify_challenge_token(&payload.challenge_token)
+        .ok_or_else(|| ApiError::Unauthorized("Invalid or expired challenge".to_string()))?;
+
+    // Code guesses count against the same per-account limit as passwords.
+    let account_key = throttle::account_key(&username);
+    if let Some(retry_after) = throttle::locked_for(&pool, std::slice::from_ref(&account_key))
+        .await?
+    {
+        return Err(ApiError::RateLimited { retry_after });
+    }
+
+    let state = sqlx::query_as::<_, TotpState>(
+        "SELECT id, totp_secret, totp_enabled, totp_last_step FROM users WHERE username = $1"
+    )
+    .bind(&username)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| ApiError::Unauthorized("Invalid or expired challenge".to_string()))?;
+
+    let secret = match (&state.totp_secret, state.totp_enabled) {
+        (Some(secret), true) => secret,
+        _ => return Err(ApiError::Unauthorized("Invalid or expired challenge".to_string())),
+    };
+
+    let is_valid = auth::verify_second_factor(&pool, state.id, secret, state.totp_last_step, &payload.code)
+        .await?;
+    if !is_valid {
+        throttle::record_failure(&pool, &account_key, &throttle::ACCOUNT_POLICY)
+            .await?;
+        return Err(ApiError::Unauthorized("Invalid code".to_string()));
+    }
+
+    throttle::reset(&pool, &account_key).await?;
+
+    let tokens = issue_tokens(&pool, state.id, None).await?;
+    Ok(token_response(tokens, payload.use_cookies))
+}
+
+/// Exchange a refresh token for a new access token
+///
+/// The refresh token is rotated on every use. Presenting a token that was
+/// already used revokes every token issued from the same login. Without a
+/// body, the refresh token cookie is used and the new session is set in cookies.
+/// A body that is present but malformed is rejected rather than ignored.
+#[utoipa::path(
+    post,
+    path = "/token/refresh",
+    request_body(content = RefreshPayload, description = "Omit to refresh a cookie session"),
+    responses(
+        (status = 200, description = "Tokens refreshed", body = LoginResponse),
+        (status = 400, description = "Malformed body", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 415, description = "Body is not JSON", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Body does not match the schema", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Invalid, expired or reused refresh token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing or invalid CSRF token, account disabled, password reset required or email address not verified", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn refresh_token_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    headers: HeaderMap,
+    OptionalJson(payload): OptionalJson<RefreshPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (refresh_token, use_cookies) = match payload {
+        Some(payload) => (payload.refresh_token, false),
+        None => {
+            let token = session::cookie_value(&headers, session::REFRESH_COOKIE)
+                .ok_or_else(|| ApiError::Unauthorized("Missing refresh token".to_string()))?;
+            session::check_csrf(&headers)?;
+            (token, true)
+        }
+    };
+
+    let stored = sqlx::query_as::<_, RefreshToken>(
+        "SELECT id, user_id, family_id, expires_at, used_at, revoked_at
+         FROM refresh_tokens WHERE token_hash = $1"
+    )
+    .bind(auth::hash_token(&refresh_token))
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| ApiError::Unauthorized("Invalid refresh token".to_string()))?;
+
+    if stored.revoked_at.is_some() {
+        return Err(ApiError::Unauthorized("Refresh token revoked".to_string()));
+    }
+    if stored.used_at.is_some() {
+        // A rotated token was replayed: assume it was stolen and kill the whole family.
+        auth::revoke_refresh_family(&pool, &stored.family_id).await?;
+        return Err(ApiError::Unauthorized("Refresh token reuse detected".to_string()));
+    }
+    if stored.expires_at <= Utc::now() {
+        return Err(ApiError::Unauthorized("Refresh token expired".to_string()));
+    }
+
+    // Claim the token atomically so two concurrent refreshes cannot both succeed.
+    let claimed = sqlx::query(
+        "UPDATE refresh_tokens SET used_at = NOW()
+         WHERE id = $1 AND used_at IS NULL AND revoked_at IS NULL"
+    )
+    .bind(stored.id)
+    .execute(&pool)
+    .await?;
+
+    if claimed.rows_affected() == 0 {
+        auth::revoke_refresh_family(&pool, &stored.family_id).await?;
+        return Err(ApiError::Unauthorized("Refresh token reuse detected".to_string()));
+    }
+
+    let tokens = issue_tokens(&pool, stored.user_id, Some(&stored.family_id)).await?;
+    Ok(token_response(tokens, use_cookies))
+}
+
+/// Log out the current session
+///
+/// Revokes the presented access token and, if given, the refresh token issued with it.
+/// Session cookies are cleared and the refresh token cookie is revoked as well.
+#[utoipa::path(
+    post,
+    path = "/logout",
+    request_body = LogoutPayload,
+    responses(
+        (status = 204, description = "Logged out"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn logout_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    headers: HeaderMap,
+    OptionalJson(payload): OptionalJson<LogoutPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Credential::Session { jti, exp } = &auth_user.credential else {
+        return Err(ApiError::BadRequest("Personal access tokens are revoked through /tokens".to_string()));
+    };
+
+    // Drop revocations that have outlived their token while we are here.
+    sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+        .execute(&pool)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO revoked_tokens (jti, expires_at)
+         VALUES ($1, to_timestamp($2))
+         ON CONFLICT (jti) DO NOTHING"
+    )
+    .bind(jti)
+    .bind(*exp as f64)
+    .execute(&pool)
+    .await?;
+
+    let refresh_tokens = payload
+        .and_then(|p| p.refresh_token)
+        .into_iter()
+        .chain(session::cookie_value(&headers, session::REFRESH_COOKIE));
+    for refresh_token in refresh_tokens {
+        // Only revoke the family if the refresh token belongs to the caller.
+        sqlx::query(
+            "UPDATE refresh_tokens r SET revoked_at = NOW()
+             FROM users u
+             WHERE r.family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = $1)
+             AND r.user_id = u.id
+             AND u.username = $2
+             AND r.revoked_at IS NULL"
+        )
+        .bind(auth::hash_token(&refresh_token))
+        .bind(&auth_user.username)
+        .execute(&pool)
+        .await?;
+    }
+
+    Ok((AppendHeaders(session::clear_session_cookies()), StatusCode::NO_CONTENT))
+}
+
+/// Log out all sessions of the current user
+///
+/// Bumps the user's token generation, which invalidates every access token
+/// issued so far, revokes all of the user's refresh tokens and deletes their
+/// personal access tokens.
+#[utoipa::path(
+    post,
+    path = "/logout/all",
+    responses(
+        (status = 204, description = "All sessions logged out"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn logout_all_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    auth::revoke_all_sessions(&pool, &auth_user.username)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Create a personal access token
+///
+/// The raw token is only returned in this response; store it securely.
+#[utoipa::path(
+    post,
+    path = "/tokens",
+    request_body = NewPersonalAccessToken,
+    responses(
+        (status = 200, description = "Token created", body = CreatedPersonalAccessToken),
+        (status = 400, description = "Unknown scope or empty name", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn create_token_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    JsonBody(payload): JsonBody<NewPersonalAccessToken>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    if payload.name.trim().is_empty() {
+        return Err(ApiError::BadRequest("Token name must not be empty".to_string()));
+    }
+    if payload.scopes.is_empty() {
+        return Err(ApiError::BadRequest("At least one scope is required".to_string()));
+    }
+    if let Some(unknown) = payload.scopes.iter().find(|s| !auth::SCOPES.contains(&s.as_str())) {
+        return Err(ApiError::BadRequest(format!("Unknown scope: {}", unknown)));
+    }
+
+    let token = format!("{}{}", auth::PAT_PREFIX, auth::generate_token());
+    let details = sqlx::query_as::<_, PersonalAccessToken>(
+        "INSERT INTO personal_access_tokens (user_id, name, token_hash, scopes)
+         SELECT id, $2, $3, $4 FROM users WHERE username = $1
+         RETURNING id, name, scopes, created_at, last_used_at"
+    )
+    .bind(&auth_user.username)
+    .bind(payload.name.trim())
+    .bind(auth::hash_token(&token))
+    .bind(&payload.scopes)
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(CreatedPersonalAccessToken { details, token }))
+}
+
+/// List the current user's personal access tokens
+#[utoipa::path(
+    get,
+    path = "/tokens",
+    responses(
+        (status = 200, description = "List of tokens", body = [PersonalAccessToken]),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn list_tokens_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    let tokens = sqlx::query_as::<_, PersonalAccessToken>(
+        "SELECT p.id, p.name, p.scopes, p.created_at, p.last_used_at
+         FROM personal_access_tokens p
+         JOIN users u ON p.user_id = u.id
+         WHERE u.username = $1
+         ORDER BY p.created_at"
+    )
+    .bind(&auth_user.username)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(tokens))
+}
+
+/// Revoke a personal access token
+#[utoipa::path(
+    delete,
+    path = "/tokens/{id}",
+    params(
+        ("id" = i32, Path, description = "Token ID")
+    ),
+    responses(
+        (status = 204, description = "Token revoked"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Token not found or not owned by you", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn revoke_token_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    let result = sqlx::query(
+        "DELETE FROM personal_access_tokens p
+         USING users u
+         WHERE p.id = $1
+         AND p.user_id = u.id
+         AND u.username = $2"
+    )
+    .bind(id)
+    .bind(&auth_user.username)
+    .execute(&pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        Err(ApiError::NotFound(format!("Token with id {} not found or not owned by you", id)))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+/// Start two-factor enrollment
+///
+/// Generates a new TOTP secret. 2FA is not enforced until the secret is
+/// confirmed with a valid code at `/2fa/verify`.
+#[utoipa::path(
+    post,
+    path = "/2fa/enroll",
+    responses(
+        (status = 200, description = "Secret generated", body = TotpEnrollment),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "2FA is already enabled", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn two_factor_enroll_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    let secret = totp::generate_secret();
+    // Only replace a pending secret, never an enabled one.
+    let result = sqlx::query(
+        "UPDATE users SET totp_secret = $1, totp_last_step = NULL
+         WHERE username = $2 AND totp_enabled = FALSE"
+    )
+    .bind(&secret)
+    .bind(&auth_user.username)
+    .execute(&pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::Conflict("Two-factor authentication is already enabled".to_string()));
+    }
+
+    Ok(Json(TotpEnrollment {
+        otpauth_uri: totp::otpauth_uri(&auth_user.username, &secret),
+        secret,
+    }))
+}
+
+/// Confirm two-factor enrollment
+///
+/// Enables 2FA once a code from the authenticator app is verified and returns
+/// one-time recovery codes. They are only shown in this response.
+#[utoipa::path(
+    post,
+    path = "/2fa/verify",
+    request_body = TwoFactorCodePayload,
+    responses(
+        (status = 200, description = "2FA enabled", body = RecoveryCodes),
+        (status = 400, description = "No enrollment in progress", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Unauthorized or invalid code", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "2FA is already enabled", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn two_factor_verify_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    JsonBody(payload): JsonBody<TwoFactorCodePayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    let state = sqlx::query_as::<_, TotpState>(
+        "SELECT id, totp_secret, totp_enabled, totp_last_step FROM users WHERE username = $1"
+    )
+    .bind(&auth_user.username)
+    .fetch_one(&pool)
+    .await?;
+
+    if state.totp_enabled {
+        return Err(ApiError::Conflict("Two-factor authentication is already enabled".to_string()));
+    }
+    let secret = state
+        .totp_secret
+        .ok_or_else(|| ApiError::BadRequest("Start enrollment at /2fa/enroll first".to_string()))?;
+    let step = totp::verify_code(&secret, &payload.code, state.totp_last_step)
+        .ok_or_else(|| ApiError::Unauthorized("Invalid code".to_string()))?;
+
+    let recovery_codes = totp::generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| auth::hash_token(&totp::normalize_recovery_code(code)))
+        .collect();
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE users SET totp_enabled = TRUE, totp_last_step = $1 WHERE id = $2")
+        .bind(step)
+        .bind(state.id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(state.id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO recovery_codes (user_id, code_hash)
+         SELECT $1, UNNEST($2::TEXT[])"
+    )
+    .bind(state.id)
+    .bind(&code_hashes)
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Disable two-factor authentication
+///
+/// Requires a current TOTP code or an unused recovery code.
+#[utoipa::path(
+    post,
+    path = "/2fa/disable",
+    request_body = TwoFactorCodePayload,
+    responses(
+        (status = 204, description = "2FA disabled"),
+        (status = 400, description = "2FA is not enabled", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Unauthorized or invalid code", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many failed attempts", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn two_factor_disable_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    JsonBody(payload): JsonBody<TwoFactorCodePayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    let state = sqlx::query_as::<_, TotpState>(
+        "SELECT id, totp_secret, totp_enabled, totp_last_step FROM users WHERE username = $1"
+    )
+    .bind(&auth_user.username)
+    .fetch_one(&pool)
+    .await?;
+
+    let secret = match (&state.totp_secret, state.totp_enabled) {
+        (Some(secret), true) => secret,
+        _ => return Err(ApiError::BadRequest("Two-factor authentication is not enabled".to_string())),
+    };
+
+    // A stolen session must not be able to guess codes without limit.
+    let account_key = throttle::account_key(&auth_user.username);
+    if let Some(retry_after) = throttle::locked_for(&pool, std::slice::from_ref(&account_key)).await? {
+        return Err(ApiError::RateLimited { retry_after });
+    }
+
+    let is_valid = auth::verify_second_factor(&pool, state.id, secret, state.totp_last_step, &payload.code)
+        .await?;
+    if !is_valid {
+        throttle::record_failure(&pool, &account_key, &throttle::ACCOUNT_POLICY).await?;
+        return Err(ApiError::Unauthorized("Invalid code".to_string()));
+    }
+    throttle::reset(&pool, &account_key).await?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "UPDATE users SET totp_enabled = FALSE, totp_secret = NULL, totp_last_step = NULL
+         WHERE id = $1"
+    )
+    .bind(state.id)
+    .execute(&mut tx)
+    .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(state.id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change the password of the current user
+///
+/// Logs out every session of the user and deletes their personal access tokens,
+/// since any of them may belong to whoever learned the old password, and
+/// returns a new session for this client. A cookie session gets new cookies.
+#[utoipa::path(
+    post,
+    path = "/password/change",
+    request_body = ChangePasswordPayload,
+    responses(
+        (status = 200, description = "Password changed", body = LoginResponse),
+        (status = 401, description = "Unauthorized or wrong current password", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "New password does not meet the password policy", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many failed attempts", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn change_password_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<ChangePasswordPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    // A stolen session must not be able to guess the password without limit.
+    let account_key = throttle::account_key(&auth_user.username);
+    if let Some(retry_after) = throttle::locked_for(&pool, std::slice::from_ref(&account_key)).await? {
+        return Err(ApiError::RateLimited { retry_after });
+    }
+
+    let current_hash = sqlx::query_scalar::<_, String>("SELECT password FROM users WHERE username = $1")
+        .bind(&auth_user.username)
+        .fetch_one(&pool)
+        .await?;
+
+    let is_valid = password::verify_password(&payload.current_password, &current_hash)?;
+    if !is_valid {
+        throttle::record_failure(&pool, &account_key, &throttle::ACCOUNT_POLICY).await?;
+        return Err(ApiError::Unauthorized("Current password is incorrect".to_string()));
+    }
+    throttle::reset(&pool, &account_key).await?;
+
+    let hashed_password = password::hash_password(&payload.new_password)?;
+
+    sqlx::query("UPDATE users SET password = $1 WHERE username = $2")
+        .bind(hashed_password)
+        .bind(&auth_user.username)
+        .execute(&pool)
+        .await?;
+
+    auth::revoke_all_sessions(&pool, &auth_user.username).await?;
+    let user_id = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = $1")
+        .bind(&auth_user.username)
+        .fetch_one(&pool)
+        .await?;
+
+    let tokens = issue_tokens(&pool, user_id, None).await?;
+    // Without a Bearer header the request was authenticated by the session cookie
+    let use_cookies = auth::bearer_token(&headers).is_none();
+    Ok(token_response(tokens, use_cookies))
+}
+
+/// Delete the current user's account and all of their data
+///
+/// The password is checked again before anything is removed. Accounts created
+/// through an identity provider that never set a password confirm with a
+/// two-factor code instead, or by signing in with the provider again within
+/// the last few minutes.
+#[utoipa::path(
+    delete,
+    path = "/account",
+    request_body = DeleteAccountPayload,
+    responses(
+        (status = 204, description = "Account deleted"),
+        (status = 401, description = "Unauthorized, or wrong password or code", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login, or a recent sign-in with the identity provider", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many failed attempts", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn delete_account_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    JsonBody(payload): JsonBody<DeleteAccountPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    // A stolen session must not be able to guess the password without limit.
+    let account_key = throttle::account_key(&auth_user.username);
+    if let Some(retry_after) = throttle::locked_for(&pool, std::slice::from_ref(&account_key)).await? {
+        return Err(ApiError::RateLimited { retry_after });
+    }
+
+    let (user_id, current_hash, password_set, totp_secret, totp_last_step) =
+        sqlx::query_as::<_, (i32, String, bool, Option<String>, Option<i64>)>(
+            "SELECT id, password, password_set,
+                    CASE WHEN totp_enabled THEN totp_secret END, totp_last_step
+             FROM users WHERE username = $1"
+        )
+        .bind(&auth_user.username)
+        .fetch_one(&pool)
+        .await?;
+
+    let is_valid = if password_set {
+        password::verify_password(payload.password.as_deref().unwrap_or_default(), &current_hash)?
+    } else if let (Some(secret), Some(code)) = (&totp_secret, &payload.code) {
+        auth::verify_second_factor(&pool, user_id, secret, totp_last_step, code).await?
+    } else {
+        // Nothing to check locally, so the provider has to vouch for the user
+        let recent_login = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (
+                 SELECT 1 FROM user_identities
+                 WHERE user_id = $1 AND last_login_at > NOW() - make_interval(mins => $2)
+             )"
+        )
+        .bind(user_id)
+        .bind(auth::REAUTH_WINDOW_MINUTES)
+        .fetch_one(&pool)
+        .await?;
+        if !recent_login {
+            return Err(ApiError::Forbidden(
+                "Sign in with your identity provider again to delete this account".to_string(),
+            ));
+        }
+        true
+    };
+    if !is_valid {
+        throttle::record_failure(&pool, &account_key, &throttle::ACCOUNT_POLICY).await?;
+        let message = if password_set { "Password is incorrect" } else { "Invalid code" };
+        return Err(ApiError::Unauthorized(message.to_string()));
+    }
+
+    // Todos, tokens, codes and linked identities are removed by ON DELETE CASCADE.
+    sqlx::query("DELETE FROM users WHERE username = $1")
+        .bind(&auth_user.username)
+        .execute(&pool)
+        .await?;
+
+    // Do not let a lockout carry over to a new account with the same name.
+    throttle::reset(&pool, &throttle::account_key(&auth_user.username))
+        .await?;
+
+    Ok((AppendHeaders(session::clear_session_cookies()), StatusCode::NO_CONTENT))
+}
+
+/// Download a copy of all data stored about the current user
+///
+/// Returns a single JSON document with the profile, todos, personal access
+/// tokens (without their secrets) and linked identities.
+#[utoipa::path(
+    get,
+    path = "/account/export",
+    responses(
+        (status = 200, description = "Account data archive", body = AccountExport),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn export_account_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    // Read everything from one snapshot so the parts are consistent.
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut tx)
+        .await?;
+
+    let profile = sqlx::query_as::<_, UserProfile>(
+        "SELECT id, username, email, email_verified_at, role, totp_enabled, timezone,
+                complete_children_with_parent, auto_complete_parents
+         FROM users WHERE username = $1"
+    )
+    .bind(&auth_user.username)
+    .fetch_one(&mut tx)
+    .await?;
+
+    let todos = sqlx::query_as::<_, Todo>(
+        &format!("SELECT {} FROM todos t WHERE t.user_id = $1 ORDER BY t.id", TODO_COLUMNS)
+    )
+    .bind(profile.id)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let tags = sqlx::query_as::<_, Tag>(
+        "SELECT tg.id, tg.name, tg.color,
+                (SELECT COUNT(*) FROM todo_tags tt WHERE tt.tag_id = tg.id) AS todo_count
+         FROM tags tg WHERE tg.user_id = $1 ORDER BY tg.id"
+    )
+    .bind(profile.id)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let projects = sqlx::query_as::<_, Project>(&format!(
+        "SELECT {} FROM projects p WHERE p.user_id = $1 ORDER BY p.id",
+        projects::PROJECT_COLUMNS
+    ))
+    .bind(profile.id)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let personal_access_tokens = sqlx::query_as::<_, PersonalAccessToken>(
+        "SELECT id, name, scopes, created_at, last_used_at
+         FROM personal_access_tokens WHERE user_id = $1 ORDER BY created_at"
+    )
+    .bind(profile.id)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let linked_identities = sqlx::query_as::<_, LinkedIdentity>(
+        "SELECT issuer, subject, email, created_at
+         FROM user_identities WHERE user_id = $1 ORDER BY created_at"
+    )
+    .bind(profile.id)
+    .fetch_all(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let exported_at = Utc::now();
+    let filename = format!(
+        "attachment; filename=\"account-export-{}.json\"",
+        exported_at.format("%Y%m%d")
+    );
+    let export = AccountExport {
+        format_version: 1,
+        exported_at,
+        profile,
+        todos,
+        tags,
+        projects,
+        personal_access_tokens,
+        linked_identities,
+    };
+
+    Ok((AppendHeaders([(header::CONTENT_DISPOSITION, filename)]), Json(export)))
+}
+
+/// Request a password reset link
+///
+/// Answers 202 whether or not the user exists, so the response does not reveal it.
+/// The link only goes to a verified email address. Requests are rate-limited
+/// per account and per client address.
+#[utoipa::path(
+    post,
+    path = "/password/forgot",
+    request_body = ForgotPasswordPayload,
+    responses(
+        (status = 202, description = "A reset link was sent if the account exists"),
+        (status = 429, description = "Too many reset requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn forgot_password_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(mailer): Extension<Mailer>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    JsonBody(payload): JsonBody<ForgotPasswordPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Every request counts, whether or not the account exists, so the limit
+    // does not reveal which usernames are taken either.
+    let account_key = throttle::reset_account_key(&payload.username);
+    let ip_key = throttle::reset_ip_key(&throttle::client_ip(&headers, &addr));
+    if let Some(retry_after) = throttle::locked_for(&pool, &[account_key.clone(), ip_key.clone()])
+        .await?
+    {
+        return Err(ApiError::RateLimited { retry_after });
+    }
+    throttle::record_failure(&pool, &account_key, &throttle::RESET_ACCOUNT_POLICY).await?;
+    throttle::record_failure(&pool, &ip_key, &throttle::RESET_IP_POLICY).await?;
+
+    let user_id = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = $1")
+        .bind(&payload.username)
+        .fetch_optional(&pool)
+        .await?;
+
+    let Some(user_id) = user_id else {
+        return Ok(StatusCode::ACCEPTED);
+    };
+
+    send_password_reset(&pool, &mailer, user_id).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+// Create a single-use reset token and mail the link to the user. Only a
+// verified address is trusted, so without one nothing is sent.
+pub(crate) async fn send_password_reset(
+    pool: &Pool<Postgres>,
+    mailer: &Mailer,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    let to = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT CASE WHEN email_verified_at IS NOT NULL THEN email END
+         FROM users WHERE id = $1"
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    let Some(to) = to else {
+        return Ok(());
+    };
+
+    let token = auth::generate_token();
+    sqlx::query(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+         VALUES ($1, $2, NOW() + make_interval(mins => $3))"
+    )
+    .bind(user_id)
+    .bind(auth::hash_token(&token))
+    .bind(auth::PASSWORD_RESET_TTL_MINUTES)
+    .execute(pool)
+    .await?;
+
+    let reset_url = std::env::var("PASSWORD_RESET_URL")
+        .unwrap_or_else(|_| "http://localhost:3000/reset-password".to_string());
+    mailer
+        .send(Email {
+            to,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Use the link below to choose a new password. It expires in {} minutes.\n\n{}?token={}",
+                auth::PASSWORD_RESET_TTL_MINUTES, reset_url, token
+            ),
+        })
+        .await?;
+    Ok(())
+}
+
+// Create a single-use verification token for an address and mail the link to it
+pub(crate) async fn send_email_verification(
+    pool: &Pool<Postgres>,
+    mailer: &Mailer,
+    user_id: i32,
+    email: &str,
+) -> Result<(), ApiError> {
+    let token = create_email_verification(pool, user_id, email).await?;
+    mail_email_verification(mailer, email, &token).await?;
+    Ok(())
+}
+
+// Store a verification token for an address and return the token itself
+async fn create_email_verification<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: i32,
+    email: &str,
+) -> Result<String, sqlx::Error> {
+    let token = auth::generate_token();
+    sqlx::query(
+        "INSERT INTO email_verification_tokens (user_id, email, token_hash, expires_at)
+         VALUES ($1, $2, $3, NOW() + make_interval(hours => $4))"
+    )
+    .bind(user_id)
+    .bind(email)
+    .bind(auth::hash_token(&token))
+    .bind(auth::EMAIL_VERIFICATION_TTL_HOURS)
+    .execute(executor)
+    .await?;
+    Ok(token)
+}
+
+async fn mail_email_verification(mailer: &Mailer, email: &str, token: &str) -> anyhow::Result<()> {
+    let verify_url = std::env::var("EMAIL_VERIFICATION_URL")
+        .unwrap_or_else(|_| "http://localhost:3000/verify-email".to_string());
+    mailer
+        .send(Email {
+            to: email.to_string(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Use the link below to confirm your email address. It expires in {} hours.\n\n{}?token={}",
+                auth::EMAIL_VERIFICATION_TTL_HOURS, verify_url, token
+            ),
+        })
+        .await
+}
+
+/// Verify an email address with a token from a verification link
+///
+/// The token can only be used once, and only confirms the address it was sent to.
+#[utoipa::path(
+    post,
+    path = "/email/verify",
+    request_body = VerifyEmailPayload,
+    responses(
+        (status = 204, description = "Email address verified"),
+        (status = 400, description = "Invalid or expired token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn verify_email_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    JsonBody(payload): JsonBody<VerifyEmailPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Claim the token atomically so it cannot be used twice.
+    let (user_id, email) = sqlx::query_as::<_, (i32, String)>(
+        "UPDATE email_verification_tokens SET used_at = NOW()
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+         RETURNING user_id, email"
+    )
+    .bind(auth::hash_token(&payload.token))
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| ApiError::BadRequest("Invalid or expired token".to_string()))?;
+
+    let verified = sqlx::query(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW())
+         WHERE id = $1 AND LOWER(email) = LOWER($2)"
+    )
+    .bind(user_id)
+    .bind(&email)
+    .execute(&pool)
+    .await?;
+
+    if verified.rows_affected() == 0 {
+        return Err(ApiError::BadRequest("Invalid or expired token".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Send a new email verification link
+///
+/// Always answers 202 so the response does not reveal whether the address is registered.
+#[utoipa::path(
+    post,
+    path = "/email/verify/resend",
+    request_body = ResendVerificationPayload,
+    responses(
+        (status = 202, description = "A link was sent if the address awaits verification"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn resend_verification_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(mailer): Extension<Mailer>,
+    JsonBody(payload): JsonBody<ResendVerificationPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = sqlx::query_as::<_, (i32, String)>(
+        "SELECT id, email FROM users
+         WHERE LOWER(email) = LOWER($1) AND email_verified_at IS NULL"
+    )
+    .bind(payload.email.trim())
+    .fetch_optional(&pool)
+    .await?;
+
+    if let Some((user_id, email)) = user {
+        send_email_verification(&pool, &mailer, user_id, &email).await?;
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Reset a password with a token from a reset link
+///
+/// The token can only be used once. All existing sessions of the user are
+/// logged out and their personal access tokens deleted afterwards.
+#[utoipa::path(
+    post,
+    path = "/password/reset",
+    request_body = ResetPasswordPayload,
+    responses(
+        (status = 204, description = "Password reset"),
+        (status = 400, description = "Invalid, used or expired token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "New password does not meet the password policy", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn reset_password_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    ValidatedJson(payload): ValidatedJson<ResetPasswordPayload>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Claim the token atomically so it cannot be used twice.
+    let user_id = sqlx::query_scalar::<_, i32>(
+        "UPDATE password_reset_tokens SET used_at = NOW()
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+         RETURNING user_id"
+    )
+    .bind(auth::hash_token(&payload.token))
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| ApiError::BadRequest("Invalid or expired reset token".to_string()))?;
+
+    let hashed_password = password::hash_password(&payload.new_password)?;
+
+    let username = sqlx::query_scalar::<_, String>(
+        "UPDATE users SET password = $1, password_set = TRUE, password_reset_required = FALSE
+         WHERE id = $2
+         RETURNING username"
+    )
+    .bind(hashed_password)
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await?;
+
+    // Any other outstanding reset links are now stale.
+    sqlx::query(
+        "UPDATE password_reset_tokens SET used_at = NOW()
+         WHERE user_id = $1 AND used_at IS NULL"
+    )
+    .bind(user_id)
+    .execute(&pool)
+    .await?;
+
+    auth::revoke_all_sessions(&pool, &username).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Public keys for verifying access tokens
+///
+/// Lets other services validate our JWTs without sharing a secret. Keys are
+/// matched by the `kid` header of the token; shared HS256 secrets are never listed.
+/// Access tokens carry the audience `todo-api`, which verifiers must require.
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "JSON Web Key Set", body = JwkSet)
+    )
+)]
+pub async fn jwks_handler() -> Json<JwkSet> {
+    Json(KEYRING.jwks())
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use axum::async_trait;
+use std::{env, path::PathBuf, sync::Arc};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Anything that can deliver an email; handlers receive it as `Extension<Mailer>`
+#[async_trait]
+pub trait MailSender: Send + Sync {
+    async fn send(&self, email: Email) -> Result<()>;
+}
+
+pub type Mailer = Arc<dyn MailSender>;
+
+// Prints messages to stdout instead of delivering them
+pub struct LogMailer;
+
+#[async_trait]
+impl MailSender for LogMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        println!(
+            "[mail] to: {}\n[mail] subject: {}\n{}",
+            email.to, email.subject, email.body
+        );
+        Ok(())
+    }
+}
+
+// Writes every message to its own file in a directory, e.g. for tests
+pub struct FileMailer {
+    pub dir: PathBuf,
+}
+
+#[async_trait]
+impl MailSender for FileMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f");
+        let path = self.dir.join(format!("{}-{}.eml", timestamp, crate::auth::generate_token()));
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+        tokio::fs::write(path, contents).await?;
+        Ok(())
+    }
+}
+
+// Pick the sender from MAIL_SENDER ("log" by default, or "file" with MAIL_DIR)
+pub fn from_env() -> Result<Mailer> {
+    match env::var("MAIL_SENDER").as_deref() {
+        Ok("file") => {
+            let dir = env::var("MAIL_DIR").unwrap_or_else(|_| "mail".to_string());
+            Ok(Arc::new(FileMailer { dir: dir.into() }))
+        }
+        Ok("log") | Err(_) => Ok(Arc::new(LogMailer)),
+        Ok(other) => anyhow::bail!("Unknown MAIL_SENDER: {}", other),
+    }
+}
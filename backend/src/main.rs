@@ -1,25 +1,41 @@
 use axum::{
-    routing::{get, post},
+    routing::{get, post, delete},
     Router,
     Extension,
     middleware,
 };
 use std::net::SocketAddr;
-use tokio;
 use dotenvy::dotenv;
 use anyhow::Result;
 use db::connect_to_db;
 
-// Import utoipa and SwaggerUi
+// Import utoipa
 use utoipa::OpenApi;
-use utoipa_swagger_ui::SwaggerUi;
-use tower_http::cors::{CorsLayer, Any};
-use axum::http::HeaderValue;
+use tower_http::cors::CorsLayer;
+use axum::http::{header, HeaderName, HeaderValue, Method};
 
 mod db;
 mod models;
 mod handlers;
 mod auth;
+mod totp;
+mod mailer;
+mod password;
+mod throttle;
+mod admin;
+mod keys;
+mod oidc;
+mod session;
+mod sort;
+mod markdown;
+mod tags;
+mod projects;
+mod subtasks;
+mod rank;
+mod recurrence;
+mod extract;
+mod validation;
+mod error;
 
 // Define the API documentation without security directives for now
 #[derive(OpenApi)]
@@ -28,26 +44,106 @@ mod auth;
         handlers::get_all_todos_handler,
         handlers::create_todo_handler,
         handlers::get_todo_handler,
+        handlers::get_todo_tree_handler,
         handlers::update_todo_handler,
+        handlers::move_todo_handler,
+        handlers::todo_occurrences_handler,
         handlers::delete_todo_handler,
         handlers::register_handler,
-        handlers::login_handler
+        handlers::me_handler,
+        handlers::update_profile_handler,
+        handlers::login_handler,
+        handlers::login_two_factor_handler,
+        handlers::refresh_token_handler,
+        handlers::logout_handler,
+        handlers::logout_all_handler,
+        handlers::create_token_handler,
+        handlers::list_tokens_handler,
+        handlers::revoke_token_handler,
+        handlers::two_factor_enroll_handler,
+        handlers::two_factor_verify_handler,
+        handlers::two_factor_disable_handler,
+        handlers::change_password_handler,
+        handlers::forgot_password_handler,
+        handlers::reset_password_handler,
+        handlers::verify_email_handler,
+        handlers::resend_verification_handler,
+        handlers::delete_account_handler,
+        handlers::export_account_handler,
+        tags::list_tags_handler,
+        tags::create_tag_handler,
+        tags::update_tag_handler,
+        tags::delete_tag_handler,
+        projects::list_projects_handler,
+        projects::create_project_handler,
+        projects::update_project_handler,
+        projects::delete_project_handler,
+        admin::list_users_handler,
+        admin::disable_user_handler,
+        admin::enable_user_handler,
+        admin::force_password_reset_handler,
+        admin::delete_user_handler,
+        handlers::jwks_handler,
+        oidc::oidc_login_handler,
+        oidc::oidc_link_handler,
+        oidc::oidc_callback_handler
     ),
     components(
         schemas(
             models::Todo,
             models::NewTodo,
             models::UpdateTodo,
-            models::User,
+            models::MoveTodoPayload,
+            models::RecurrencePreview,
+            models::UserProfile,
+            models::UpdateProfilePayload,
+            models::RegisterResponse,
             models::RegisterPayload,
             models::LoginPayload,
             models::TodoQueryParams,
-            models::TokenResponse
+            models::DueWindow,
+            models::RenderFormat,
+            models::TagMatch,
+            models::Tag,
+            models::NewTag,
+            models::UpdateTag,
+            models::Project,
+            models::NewProject,
+            models::UpdateProject,
+            models::ProjectDeleteMode,
+            models::TodoInclude,
+            models::TokenResponse,
+            models::RefreshPayload,
+            models::LogoutPayload,
+            models::PersonalAccessToken,
+            models::NewPersonalAccessToken,
+            models::CreatedPersonalAccessToken,
+            models::LoginResponse,
+            models::CookieSession,
+            models::TwoFactorChallenge,
+            models::TwoFactorLoginPayload,
+            models::TwoFactorCodePayload,
+            models::TotpEnrollment,
+            models::RecoveryCodes,
+            models::ChangePasswordPayload,
+            models::ForgotPasswordPayload,
+            models::ResetPasswordPayload,
+            models::VerifyEmailPayload,
+            models::ResendVerificationPayload,
+            models::DeleteAccountPayload,
+            models::ProblemDetails,
+            models::AccountExport,
+            models::LinkedIdentity,
+            models::AdminUserView,
+            models::Jwk,
+            models::JwkSet,
+            models::OidcAuthorization
         )
     ),
     tags(
         (name = "todos", description = "Todo management endpoints"),
-        (name = "auth", description = "Authentication endpoints")
+        (name = "auth", description = "Authentication endpoints"),
+        (name = "admin", description = "User administration endpoints")
     )
 )]
 struct ApiDoc;
@@ -62,6 +158,13 @@ async fn main() -> Result<()> {
     dotenv().ok();
     let database_url = std::env::var("DATABASE_URL")?;
     let pool = connect_to_db(&database_url).await?;
+    let mailer = mailer::from_env()?;
+    // Fail at startup rather than on the first login if the keys or the
+    // password hashing parameters are misconfigured
+    once_cell::sync::Lazy::force(&keys::KEYRING);
+    once_cell::sync::Lazy::force(&password::ARGON2_PARAMS);
+    // Hash the dummy password now so the first unknown username is not slower
+    once_cell::sync::Lazy::force(&password::DUMMY_HASH);
 
     println!("Successfully connected to the database!");
 
@@ -76,7 +179,14 @@ async fn main() -> Result<()> {
     // Public routes
     let public_routes = Router::new()
         .route("/register", post(handlers::register_handler))
-        .route("/login", post(handlers::login_handler));
+        .route("/login", post(handlers::login_handler))
+        .route("/login/2fa", post(handlers::login_two_factor_handler))
+        .route("/token/refresh", post(handlers::refresh_token_handler))
+        .route("/password/forgot", post(handlers::forgot_password_handler))
+        .route("/password/reset", post(handlers::reset_password_handler))
+        .route("/email/verify", post(handlers::verify_email_handler))
+        .route("/email/verify/resend", post(handlers::resend_verification_handler))
+        .route("/.well-known/jwks.json", get(handlers::jwks_handler));
 
     // Protected routes requiring authentication
     let protected_routes = Router::new()
@@ -91,6 +201,24 @@ async fn main() -> Result<()> {
                 .put(handlers::update_todo_handler)
                 .delete(handlers::delete_todo_handler)
         )
+        .route("/todos/:id/tree", get(handlers::get_todo_tree_handler))
+        .route("/todos/:id/move", post(handlers::move_todo_handler))
+        .route("/todos/:id/occurrences", get(handlers::todo_occurrences_handler))
+        .route("/me", get(handlers::me_handler).put(handlers::update_profile_handler))
+        .route("/logout", post(handlers::logout_handler))
+        .route("/logout/all", post(handlers::logout_all_handler))
+        .route(
+            "/tokens",
+            get(handlers::list_tokens_handler)
+                .post(handlers::create_token_handler)
+        )
+        .route("/tokens/:id", delete(handlers::revoke_token_handler))
+        .route("/2fa/enroll", post(handlers::two_factor_enroll_handler))
+        .route("/2fa/verify", post(handlers::two_factor_verify_handler))
+        .route("/2fa/disable", post(handlers::two_factor_disable_handler))
+        .route("/password/change", post(handlers::change_password_handler))
+        .route("/account", delete(handlers::delete_account_handler))
+        .route("/account/export", get(handlers::export_account_handler))
         .layer(middleware::from_fn(auth::require_auth));
 
     // Combine routes:
@@ -99,25 +227,120 @@ async fn main() -> Result<()> {
     let app = Router::new()
     .merge(public_routes)
     .merge(protected_routes)
+    .merge(tags::router())
+    .merge(projects::router())
+    .merge(admin::router())
+    .merge(oidc::router())
     // Keep only the OpenAPI JSON endpoint
     .route("/api-docs/openapi.json", get(serve_openapi))
-    .layer(Extension(pool));
+    .layer(Extension(pool))
+    .layer(Extension(mailer));
 
 
     // Add CORS layer here
     let cors = CorsLayer::new()
     .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-    .allow_methods(Any)
-    .allow_headers(Any);
+    // Credentials are allowed so the frontend can use cookie sessions, which rules out wildcards
+    .allow_credentials(true)
+    .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+    .allow_headers([
+        header::AUTHORIZATION,
+        header::CONTENT_TYPE,
+        HeaderName::from_static(session::CSRF_HEADER),
+    ])
+    .expose_headers([HeaderName::from_static(error::TRACE_ID_HEADER)]);
 
-    // Update app with the CORS layer
-    let app = app.layer(cors);
+    // Update app with the CORS layer; tracing wraps everything so every response gets an id
+    let app = app
+        .layer(cors)
+        .layer(middleware::from_fn(error::trace_requests));
     
     let addr = SocketAddr::from(([127, 0, 0, 1], 3001));
     println!("Server running at http://{}", addr);
     println!("API Documentation (OpenAPI JSON) available at http://{}/api-docs/openapi.json", addr);
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApiDoc;
+    use crate::models::{RegisterResponse, User};
+    use crate::password;
+    use serde_json::Value;
+    use std::collections::HashSet;
+    use utoipa::OpenApi;
+
+    fn is_secret_field(name: &str) -> bool {
+        name == "password" || name.ends_with("_password") || name.contains("hash")
+    }
+
+    // Collect secret-looking property names reachable from a schema, following $refs
+    fn secret_fields(schema: &Value, components: &Value, seen: &mut HashSet<String>, found: &mut Vec<String>) {
+        match schema {
+            Value::Object(map) => {
+                if let Some(Value::String(reference)) = map.get("$ref") {
+                    let name = reference.trim_start_matches("#/components/schemas/");
+                    if seen.insert(name.to_string()) {
+                        secret_fields(&components[name], components, seen, found);
+                    }
+                }
+                if let Some(Value::Object(properties)) = map.get("properties") {
+                    found.extend(properties.keys().filter(|name| is_secret_field(name)).cloned());
+                }
+                for value in map.values() {
+                    secret_fields(value, components, seen, found);
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    secret_fields(item, components, seen, found);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn no_response_schema_contains_a_password() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let components = &doc["components"]["schemas"];
+
+        for (path, operations) in doc["paths"].as_object().unwrap() {
+            for (method, operation) in operations.as_object().unwrap() {
+                let mut found = Vec::new();
+                secret_fields(&operation["responses"], components, &mut HashSet::new(), &mut found);
+                assert!(found.is_empty(), "{} {} responds with {:?}", method, path, found);
+            }
+        }
+    }
+
+    #[test]
+    fn register_response_does_not_contain_the_hash() {
+        let hash = password::hash_password("Correct-Horse-42").unwrap();
+        let user = User {
+            id: 1,
+            username: "john_doe".to_string(),
+            password: hash.clone(),
+            email: Some("john@example.com".to_string()),
+            email_verified_at: None,
+            role: "user".to_string(),
+            totp_enabled: false,
+            timezone: "UTC".to_string(),
+            complete_children_with_parent: true,
+            auto_complete_parents: false,
+        };
+
+        let body = serde_json::to_string(&RegisterResponse {
+            user: user.into(),
+            tokens: None,
+        })
+        .unwrap();
+
+        assert!(!body.contains(&hash));
+        assert!(!body.contains("$argon2"));
+        assert!(!body.contains("password"));
+    }
+}
@@ -0,0 +1,39 @@
+use crate::models::Todo;
+use pulldown_cmark::{html, Options, Parser};
+
+// Render user-supplied Markdown to HTML that is safe to insert into a page.
+// Raw HTML in the source is passed through the parser and then stripped of
+// anything ammonia does not allow (scripts, event handlers, javascript: links).
+pub fn to_safe_html(markdown: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+    ammonia::clean(&unsafe_html)
+}
+
+// Fill in `notes_html` from `notes`
+pub fn render_notes(todo: &mut Todo) {
+    todo.notes_html = todo.notes.as_deref().map(to_safe_html);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_safe_html;
+
+    #[test]
+    fn renders_markdown() {
+        assert_eq!(to_safe_html("Oat milk, **not** almond"), "<p>Oat milk, <strong>not</strong> almond</p>\n");
+    }
+
+    #[test]
+    fn strips_scripts_and_javascript_links() {
+        let html = to_safe_html("<script>alert(1)</script>\n\n[click](javascript:alert(1)) <img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("onerror"));
+    }
+}
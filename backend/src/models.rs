@@ -1,18 +1,83 @@
-use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{postgres::PgRow, FromRow, Row};
 use utoipa::{ToSchema,IntoParams};  // Add this import
 
-#[derive(FromRow, Serialize, Deserialize, ToSchema)]  // Add ToSchema
+// A users row, including the password hash. Deliberately not `Serialize`:
+// responses use `UserProfile` instead.
+#[derive(FromRow)]
 pub struct User {
-    #[schema(example = 1)]  // Add example values
     pub id: i32,
-    #[schema(example = "john_doe")]
     pub username: String,
-    #[schema(example = "password123", write_only)]  // mark as write-only
     pub password: String,
+    pub email: Option<String>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub role: String,
+    pub totp_enabled: bool,
+    pub timezone: String,
+    pub complete_children_with_parent: bool,
+    pub auto_complete_parents: bool,
 }
 
-#[derive(FromRow, Debug, Serialize, Deserialize, ToSchema)]  // Add ToSchema
+// What clients may see about a user
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct UserProfile {
+    #[schema(example = 1)]
+    pub id: i32,
+    #[schema(example = "john_doe")]
+    pub username: String,
+    #[schema(example = "john@example.com")]
+    pub email: Option<String>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    #[schema(example = "user")]
+    pub role: String,
+    #[schema(example = false)]
+    pub totp_enabled: bool,
+    // IANA time zone used for date filters such as `due=today`
+    #[schema(example = "Europe/Berlin")]
+    pub timezone: String,
+    // Completing a todo also completes its open subtasks
+    #[schema(example = true)]
+    pub complete_children_with_parent: bool,
+    // A todo is completed once all of its subtasks are, and reopened when one is reopened
+    #[schema(example = false)]
+    pub auto_complete_parents: bool,
+}
+
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        UserProfile {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            email_verified_at: user.email_verified_at,
+            role: user.role,
+            totp_enabled: user.totp_enabled,
+            timezone: user.timezone,
+            complete_children_with_parent: user.complete_children_with_parent,
+            auto_complete_parents: user.auto_complete_parents,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateProfilePayload {
+    #[schema(example = "Europe/Berlin")]
+    pub timezone: Option<String>,
+    #[schema(example = true)]
+    pub complete_children_with_parent: Option<bool>,
+    #[schema(example = true)]
+    pub auto_complete_parents: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RegisterResponse {
+    pub user: UserProfile,
+    // Present when `login` was requested and the account may log in right away
+    pub tokens: Option<TokenResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]  // Add ToSchema
 pub struct Todo {
     #[schema(example = 1)]
     pub id: i32,
@@ -22,16 +87,105 @@ pub struct Todo {
     pub completed: bool,
     #[schema(example = 1)]
     pub user_id: i32,
+    pub due_at: Option<DateTime<Utc>>,
+    pub start_at: Option<DateTime<Utc>>,
+    // One of none, low, medium, high, urgent
+    #[schema(example = "high")]
+    pub priority: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    // Set when the todo is marked completed, cleared when it is reopened
+    pub completed_at: Option<DateTime<Utc>>,
+    // Markdown source
+    #[schema(example = "Oat milk, **not** almond")]
+    pub notes: Option<String>,
+    // Sanitized HTML rendering of `notes`, only present with `render=html`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "<p>Oat milk, <strong>not</strong> almond</p>")]
+    pub notes_html: Option<String>,
+    #[schema(example = json!(["errands", "home"]))]
+    pub tags: Vec<String>,
+    // None for todos in the inbox
+    #[schema(example = 1)]
+    pub project_id: Option<i32>,
+    // The todo this one is a subtask of
+    #[schema(example = json!(null))]
+    pub parent_id: Option<i32>,
+    // Sort key within the todo's list; compare bytewise. Change it through /todos/{id}/move
+    #[schema(example = "i")]
+    pub position: String,
+    // RFC 5545 RRULE; completing the todo creates the next occurrence
+    #[schema(example = "FREQ=WEEKLY;BYDAY=TU")]
+    pub recurrence: Option<String>,
+    // Nested subtasks, only present when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<Todo>>,
+}
+
+// Written out rather than derived: `notes_html` and `children` are never
+// columns, and `#[sqlx(default)]` would still require `Vec<Todo>` to be
+// decodable from one. Queries select `TODO_COLUMNS`.
+impl<'r> FromRow<'r, PgRow> for Todo {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Todo {
+            id: row.try_get("id")?,
+            title: row.try_get("title")?,
+            completed: row.try_get("completed")?,
+            user_id: row.try_get("user_id")?,
+            due_at: row.try_get("due_at")?,
+            start_at: row.try_get("start_at")?,
+            priority: row.try_get("priority")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+            completed_at: row.try_get("completed_at")?,
+            notes: row.try_get("notes")?,
+            notes_html: None,
+            tags: row.try_get("tags")?,
+            project_id: row.try_get("project_id")?,
+            parent_id: row.try_get("parent_id")?,
+            position: row.try_get("position")?,
+            recurrence: row.try_get("recurrence")?,
+            children: None,
+        })
+    }
 }
 
-#[derive(Deserialize, ToSchema)]  // Add ToSchema
+#[derive(Debug, Deserialize, ToSchema)]  // Add ToSchema
 pub struct NewTodo {
     #[schema(example = "Buy groceries")]
     pub title: String,
     #[schema(example = false)]
     pub completed: Option<bool>,
-    #[serde(skip)]
-    pub user_id: Option<i32>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub start_at: Option<DateTime<Utc>>,
+    // Defaults to none
+    #[schema(example = "high")]
+    pub priority: Option<String>,
+    // Markdown
+    #[schema(example = "Oat milk, **not** almond")]
+    pub notes: Option<String>,
+    // Tag names; tags that do not exist yet are created
+    #[serde(default)]
+    #[schema(example = json!(["errands"]))]
+    pub tags: Vec<String>,
+    // Omit to put the todo in the inbox
+    #[schema(example = 1)]
+    pub project_id: Option<i32>,
+    // Create the todo as a subtask of this one
+    #[schema(example = json!(null))]
+    pub parent_id: Option<i32>,
+    // RRULE such as FREQ=WEEKLY;BYDAY=TU; requires due_at, the first occurrence
+    #[schema(example = "FREQ=WEEKLY;BYDAY=TU")]
+    pub recurrence: Option<String>,
+}
+
+// For fields that can be cleared: absent leaves the value alone, null clears it
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
 
 #[derive(Debug, Deserialize, ToSchema)]  // Add ToSchema
@@ -40,14 +194,76 @@ pub struct UpdateTodo {
     pub title: Option<String>,
     #[schema(example = true)]
     pub completed: Option<bool>,
+    // null removes the due date
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<DateTime<Utc>>)]
+    pub due_at: Option<Option<DateTime<Utc>>>,
+    // null removes the start date
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<DateTime<Utc>>)]
+    pub start_at: Option<Option<DateTime<Utc>>>,
+    #[schema(example = "urgent")]
+    pub priority: Option<String>,
+    // Markdown; null removes the notes
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<String>)]
+    pub notes: Option<Option<String>>,
+    // Replaces all tags of the todo; tags that do not exist yet are created
+    #[schema(example = json!(["errands", "home"]))]
+    pub tags: Option<Vec<String>>,
+    // null moves the todo to the inbox
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<i32>, example = 2)]
+    pub project_id: Option<Option<i32>>,
+    // null turns the todo back into a top-level todo
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<i32>, example = 5)]
+    pub parent_id: Option<Option<i32>>,
+    // null stops the todo from repeating
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<String>, example = "FREQ=MONTHLY;BYMONTHDAY=1")]
+    pub recurrence: Option<Option<String>>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct OccurrenceParams {
+    // How many occurrences to return; 5 by default, at most 100
+    pub count: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RecurrencePreview {
+    #[schema(example = "FREQ=WEEKLY;BYDAY=TU")]
+    pub recurrence: String,
+    // Due dates of the occurrences after the todo's own, in order
+    pub occurrences: Vec<DateTime<Utc>>,
+}
+
+// Anchors for /todos/{id}/move; at least one is required. Both must be in
+// the same list as the todo.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MoveTodoPayload {
+    // Place the todo right before this one
+    #[schema(example = 7)]
+    pub before: Option<i32>,
+    // Place the todo right after this one
+    #[schema(example = 3)]
+    pub after: Option<i32>,
 }
 
 #[derive(Debug, Deserialize, ToSchema)]  // Add ToSchema
 pub struct RegisterPayload {
     #[schema(example = "john_doe")]
     pub username: String,
-    #[schema(example = "password123")]
+    #[schema(example = "Correct-Horse-42")]
     pub password: String,
+    // A verification link is sent to this address
+    #[schema(example = "john@example.com")]
+    pub email: Option<String>,
+    // Also log in and return tokens
+    #[serde(default)]
+    #[schema(example = false)]
+    pub login: bool,
 }
 
 #[derive(Debug, Deserialize, ToSchema)]  // Add ToSchema
@@ -56,6 +272,10 @@ pub struct LoginPayload {
     pub username: String,
     #[schema(example = "password123")]
     pub password: String,
+    // Keep the session in HttpOnly cookies instead of returning the tokens
+    #[serde(default)]
+    #[schema(example = false)]
+    pub use_cookies: bool,
 }
 
 // Add a response type for the token
@@ -63,12 +283,453 @@ pub struct LoginPayload {
 pub struct TodoQueryParams {
     #[schema(example = true)]
     pub completed: Option<bool>,
+    // Matched against the title and the notes
     #[schema(example = "grocery")]
     pub search: Option<String>,
+    // Due strictly before this instant
+    pub due_before: Option<DateTime<Utc>>,
+    // Due strictly after this instant
+    pub due_after: Option<DateTime<Utc>>,
+    // Open todos whose due date has passed (or, with false, all others)
+    #[schema(example = true)]
+    pub overdue: Option<bool>,
+    // Due within the current day or week in the user's time zone
+    pub due: Option<DueWindow>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    // Only matches completed todos
+    pub completed_before: Option<DateTime<Utc>>,
+    pub completed_after: Option<DateTime<Utc>>,
+    // Comma-separated sort keys, each optionally prefixed with '-' for descending:
+    // position (the default), priority, due_at, start_at, created_at, updated_at,
+    // completed_at, title, completed, id
+    #[schema(example = "-priority,due_at")]
+    pub sort: Option<String>,
+    // Also return the notes as sanitized HTML
+    pub render: Option<RenderFormat>,
+    // Repeat to filter by several tags, e.g. `tag=home&tag=errands`
+    #[serde(default)]
+    #[schema(example = json!(["home"]))]
+    pub tag: Vec<String>,
+    // Whether todos need all of the given tags (the default) or any of them
+    pub tag_match: Option<TagMatch>,
+    #[schema(example = 1)]
+    pub project_id: Option<i32>,
+    // Only todos without a project (true) or only those with one (false)
+    #[schema(example = true)]
+    pub inbox: Option<bool>,
+    // Only direct subtasks of this todo
+    pub parent_id: Option<i32>,
+    // Only todos that are not subtasks
+    #[schema(example = true)]
+    pub top_level: Option<bool>,
+    // `children` nests each todo's subtasks under it
+    pub include: Option<TodoInclude>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoInclude {
+    Children,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    All,
+    Any,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct RenderParams {
+    // Also return the notes as sanitized HTML
+    pub render: Option<RenderFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderFormat {
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DueWindow {
+    Today,
+    // Monday to Sunday
+    ThisWeek,
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Tag {
+    #[schema(example = 1)]
+    pub id: i32,
+    #[schema(example = "errands")]
+    pub name: String,
+    #[schema(example = "#ff9800")]
+    pub color: String,
+    // Number of todos carrying the tag
+    #[schema(example = 3)]
+    pub todo_count: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewTag {
+    #[schema(example = "errands")]
+    pub name: String,
+    // #rrggbb; a neutral grey if omitted
+    #[schema(example = "#ff9800")]
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateTag {
+    #[schema(example = "shopping")]
+    pub name: Option<String>,
+    #[schema(example = "#4caf50")]
+    pub color: Option<String>,
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct Project {
+    #[schema(example = 1)]
+    pub id: i32,
+    #[schema(example = "Household")]
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    #[schema(example = 4)]
+    pub open_count: i64,
+    #[schema(example = 12)]
+    pub completed_count: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewProject {
+    #[schema(example = "Household")]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateProject {
+    #[schema(example = "Home")]
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct DeleteProjectParams {
+    // What happens to the project's todos; moved to the inbox by default
+    pub mode: Option<ProjectDeleteMode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectDeleteMode {
+    Inbox,
+    Cascade,
 }
 
 #[derive(Serialize, ToSchema)]
 pub struct TokenResponse {
     #[schema(example = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9...")]
     pub token: String,
-}
\ No newline at end of file
+    #[schema(example = "q3Vh0XbW9kT2mZr8Lc5yPn1sJd7fAe4G6uIo0BtKxMwRlYvNh")]
+    pub refresh_token: String,
+}
+
+// Returned by /login instead of tokens when the account has 2FA enabled
+#[derive(Serialize, ToSchema)]
+pub struct TwoFactorChallenge {
+    #[schema(example = true)]
+    pub two_factor_required: bool,
+    #[schema(example = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9...")]
+    pub challenge_token: String,
+}
+
+// Returned instead of tokens when the session is kept in cookies
+#[derive(Serialize, ToSchema)]
+pub struct CookieSession {
+    // Echo this in the X-CSRF-Token header on unsafe requests
+    #[schema(example = "Zx8c1Vq0hTn5LwYb2Kd7RfPm3GsJa9Ue4Ho6Ci1XlNtQyBvMk")]
+    pub csrf_token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(TokenResponse),
+    Session(CookieSession),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TwoFactorLoginPayload {
+    #[schema(example = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9...")]
+    pub challenge_token: String,
+    // Either a current TOTP code or one of the recovery codes
+    #[schema(example = "123456")]
+    pub code: String,
+    #[serde(default)]
+    #[schema(example = false)]
+    pub use_cookies: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TwoFactorCodePayload {
+    #[schema(example = "123456")]
+    pub code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TotpEnrollment {
+    #[schema(example = "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP")]
+    pub secret: String,
+    #[schema(example = "otpauth://totp/Todo%20App:john_doe?secret=JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP&issuer=Todo%20App&algorithm=SHA1&digits=6&period=30")]
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RecoveryCodes {
+    #[schema(example = json!(["k3v9a-0xq2m", "p8d1z-7hw4c"]))]
+    pub recovery_codes: Vec<String>,
+}
+
+// Columns of the users table needed to check a second factor
+#[derive(FromRow)]
+pub struct TotpState {
+    pub id: i32,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_last_step: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RefreshPayload {
+    #[schema(example = "q3Vh0XbW9kT2mZr8Lc5yPn1sJd7fAe4G6uIo0BtKxMwRlYvNh")]
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LogoutPayload {
+    // Optionally revoke the refresh token issued alongside the access token
+    #[schema(example = "q3Vh0XbW9kT2mZr8Lc5yPn1sJd7fAe4G6uIo0BtKxMwRlYvNh")]
+    pub refresh_token: Option<String>,
+}
+
+// Row of the refresh_tokens table needed to validate a rotation
+#[derive(FromRow)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub family_id: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct PersonalAccessToken {
+    #[schema(example = 1)]
+    pub id: i32,
+    #[schema(example = "ci-export")]
+    pub name: String,
+    #[schema(example = json!(["todos:read"]))]
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewPersonalAccessToken {
+    #[schema(example = "ci-export")]
+    pub name: String,
+    #[schema(example = json!(["todos:read", "todos:write"]))]
+    pub scopes: Vec<String>,
+}
+
+// Returned once on creation; the raw token cannot be retrieved again
+#[derive(Serialize, ToSchema)]
+pub struct CreatedPersonalAccessToken {
+    #[serde(flatten)]
+    pub details: PersonalAccessToken,
+    #[schema(example = "pat_q3Vh0XbW9kT2mZr8Lc5yPn1sJd7fAe4G6uIo0BtKxMwRlYvNh")]
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChangePasswordPayload {
+    #[schema(example = "password123")]
+    pub current_password: String,
+    #[schema(example = "correct horse battery staple")]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ForgotPasswordPayload {
+    #[schema(example = "john_doe")]
+    pub username: String,
+}
+
+// Error body (application/problem+json) returned by every endpoint
+#[derive(Serialize, ToSchema)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    #[schema(example = "about:blank")]
+    pub problem_type: String,
+    #[schema(example = "Not Found")]
+    pub title: String,
+    #[schema(example = 404)]
+    pub status: u16,
+    #[schema(example = "Todo with id 7 not found")]
+    pub detail: String,
+    // Stable machine-readable error code
+    #[schema(example = "not_found")]
+    pub code: String,
+    // Also sent in the X-Trace-Id header; quote it when reporting a problem
+    #[schema(example = "4f9c2a7e1b3d5c6a8e0f1a2b3c4d5e6f")]
+    pub trace_id: String,
+    // Messages per invalid field, for validation_failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = json!({"title": ["must not be empty"]}))]
+    pub errors: Option<std::collections::BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DeleteAccountPayload {
+    #[schema(example = "password123")]
+    pub password: Option<String>,
+    // TOTP or recovery code, for accounts without a password of their own
+    #[schema(example = "123456")]
+    pub code: Option<String>,
+}
+
+// Everything stored about a user, as returned by /account/export
+#[derive(Serialize, ToSchema)]
+pub struct AccountExport {
+    // Bumped whenever the layout of the archive changes
+    #[schema(example = 1)]
+    pub format_version: i32,
+    pub exported_at: DateTime<Utc>,
+    pub profile: UserProfile,
+    pub todos: Vec<Todo>,
+    pub tags: Vec<Tag>,
+    pub projects: Vec<Project>,
+    pub personal_access_tokens: Vec<PersonalAccessToken>,
+    pub linked_identities: Vec<LinkedIdentity>,
+}
+
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct LinkedIdentity {
+    #[schema(example = "https://accounts.example.com")]
+    pub issuer: String,
+    #[schema(example = "248289761001")]
+    pub subject: String,
+    #[schema(example = "john@example.com")]
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyEmailPayload {
+    #[schema(example = "q3Vh0XbW9kT2mZr8Lc5yPn1sJd7fAe4G6uIo0BtKxMwRlYvNh")]
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ResendVerificationPayload {
+    #[schema(example = "john@example.com")]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ResetPasswordPayload {
+    #[schema(example = "q3Vh0XbW9kT2mZr8Lc5yPn1sJd7fAe4G6uIo0BtKxMwRlYvNh")]
+    pub token: String,
+    #[schema(example = "correct horse battery staple")]
+    pub new_password: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    // Parse the value of the users.role column
+    pub fn from_db(value: &str) -> Role {
+        match value {
+            "admin" => Role::Admin,
+            _ => Role::User,
+        }
+    }
+}
+
+// A user as seen by administrators
+#[derive(FromRow, Serialize, ToSchema)]
+pub struct AdminUserView {
+    #[schema(example = 1)]
+    pub id: i32,
+    #[schema(example = "john_doe")]
+    pub username: String,
+    #[schema(example = "user")]
+    pub role: String,
+    #[schema(example = false)]
+    pub disabled: bool,
+    #[schema(example = false)]
+    pub totp_enabled: bool,
+    #[schema(example = false)]
+    pub password_reset_required: bool,
+}
+
+// Public signing key in JSON Web Key format (RFC 7517)
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Jwk {
+    #[schema(example = "RSA")]
+    pub kty: String,
+    #[schema(example = "2025-10")]
+    pub kid: String,
+    #[schema(example = "RS256")]
+    pub alg: String,
+    #[serde(rename = "use")]
+    #[schema(example = "sig")]
+    pub key_use: String,
+    // RSA modulus and exponent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    // Ed25519 curve and public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OidcAuthorization {
+    #[schema(example = "https://idp.example.com/authorize?response_type=code&client_id=todo-app&...")]
+    pub authorization_url: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct OidcLoginParams {
+    // Start a cookie session after the callback instead of returning tokens
+    #[serde(default)]
+    pub use_cookies: bool,
+}
+
+// Query parameters the identity provider sends back to /oidc/callback
+#[derive(Deserialize, IntoParams)]
+pub struct OidcCallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
@@ -0,0 +1,455 @@
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::Extension,
+    http::{HeaderMap, HeaderName},
+    middleware,
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use cookie::time::Duration;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::env;
+use tokio::sync::OnceCell;
+use crate::auth::{self, AuthenticatedUser};
+use crate::error::ApiError;
+use crate::extract::Query;
+use crate::handlers::issue_tokens;
+use crate::models::{CookieSession, OidcAuthorization, OidcCallbackParams, OidcLoginParams};
+use crate::password;
+use crate::session;
+use crate::validation;
+
+// Authorization requests must be completed within 10 minutes
+const LOGIN_STATE_TTL_MINUTES: i32 = 10;
+
+// OpenID Connect settings. OIDC_ISSUER, OIDC_CLIENT_ID and OIDC_REDIRECT_URI are
+// required to enable the flow; OIDC_CLIENT_SECRET is optional for public clients.
+// OIDC_POST_LOGIN_REDIRECT, if set, receives the tokens in the URL fragment
+// instead of a JSON response.
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+    pub post_login_redirect: Option<String>,
+}
+
+pub static OIDC_CONFIG: Lazy<Option<OidcConfig>> = Lazy::new(|| {
+    Some(OidcConfig {
+        issuer: env::var("OIDC_ISSUER").ok()?.trim_end_matches('/').to_string(),
+        client_id: env::var("OIDC_CLIENT_ID").ok()?,
+        client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+        redirect_uri: env::var("OIDC_REDIRECT_URI").ok()?,
+        scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid profile email".to_string()),
+        post_login_redirect: env::var("OIDC_POST_LOGIN_REDIRECT").ok(),
+    })
+});
+
+// The parts of the provider's discovery document we use
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+static PROVIDER_METADATA: OnceCell<ProviderMetadata> = OnceCell::const_new();
+
+#[derive(Debug, Deserialize)]
+struct TokenEndpointResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    preferred_username: Option<String>,
+}
+
+// Routes for the OIDC flow; /oidc/link needs an existing session
+pub fn router() -> Router {
+    let protected = Router::new()
+        .route("/oidc/link", post(oidc_link_handler))
+        .layer(middleware::from_fn(auth::require_auth));
+
+    Router::new()
+        .route("/oidc/login", get(oidc_login_handler))
+        .route("/oidc/callback", get(oidc_callback_handler))
+        .merge(protected)
+}
+
+fn config() -> Result<&'static OidcConfig, ApiError> {
+    OIDC_CONFIG
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("OpenID Connect login is not configured".to_string()))
+}
+
+async fn provider_metadata(config: &OidcConfig) -> Result<&'static ProviderMetadata> {
+    PROVIDER_METADATA
+        .get_or_try_init(|| async {
+            let url = format!("{}/.well-known/openid-configuration", config.issuer);
+            let metadata: ProviderMetadata = reqwest::get(&url)
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .with_context(|| format!("reading {}", url))?;
+            if metadata.issuer.trim_end_matches('/') != config.issuer {
+                bail!("discovery document is for issuer {}", metadata.issuer);
+            }
+            Ok(metadata)
+        })
+        .await
+}
+
+fn oidc_error(err: anyhow::Error) -> ApiError {
+    ApiError::BadGateway(format!("OIDC provider error: {:#}", err))
+}
+
+// PKCE S256 code challenge for a verifier (RFC 7636)
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+// Store a new authorization request and build the URL to send the browser to,
+// together with the cookie that ties the request to this browser
+async fn start_authorization(
+    pool: &Pool<Postgres>,
+    link_user_id: Option<i32>,
+    use_cookies: bool,
+) -> Result<(String, (HeaderName, String)), ApiError> {
+    let config = config()?;
+    let metadata = provider_metadata(config).await.map_err(oidc_error)?;
+
+    let state = auth::generate_token();
+    let nonce = auth::generate_token();
+    let code_verifier = auth::generate_token();
+
+    sqlx::query(
+        "INSERT INTO oidc_login_states (state, code_verifier, nonce, link_user_id, use_cookies, expires_at)
+         VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(mins => $6))"
+    )
+    .bind(&state)
+    .bind(&code_verifier)
+    .bind(&nonce)
+    .bind(link_user_id)
+    .bind(use_cookies)
+    .bind(LOGIN_STATE_TTL_MINUTES)
+    .execute(pool)
+    .await?;
+
+    let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+        .map_err(|err| oidc_error(err.into()))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_uri)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &code_challenge(&code_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    let cookie = session::oidc_state_cookie(&state, Duration::minutes(LOGIN_STATE_TTL_MINUTES.into()));
+    Ok((url.to_string(), cookie))
+}
+
+// Redeem the authorization code and return the verified ID token claims
+async fn exchange_code(config: &OidcConfig, code: &str, code_verifier: &str, nonce: &str) -> Result<IdTokenClaims> {
+    let metadata = provider_metadata(config).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_uri.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+
+    let client = reqwest::Client::new();
+    let response: TokenEndpointResponse = client
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("reading token response")?;
+
+    let jwks: JwkSet = client
+        .get(&metadata.jwks_uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("reading provider JWKS")?;
+
+    let header = decode_header(&response.id_token)?;
+    // Only asymmetric algorithms; an HS256 ID token would be keyed by our client secret
+    if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+        bail!("unsupported ID token algorithm {:?}", header.alg);
+    }
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or_else(|| anyhow!("no provider key matches the ID token"))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    let claims = decode::<IdTokenClaims>(&response.id_token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        bail!("ID token nonce does not match");
+    }
+    Ok(claims)
+}
+
+// Derive a free local username from the provider's profile
+async fn available_username(pool: &Pool<Postgres>, claims: &IdTokenClaims) -> Result<String, sqlx::Error> {
+    let base: String = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref().and_then(|email| email.split('@').next()))
+        .unwrap_or("user")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        .take(24)
+        .collect::<String>()
+        .to_lowercase();
+    let base = if base.len() < validation::USERNAME_MIN_LEN { "user".to_string() } else { base };
+
+    let mut candidate = base.clone();
+    loop {
+        let taken = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM users WHERE username = $1)")
+            .bind(&candidate)
+            .fetch_one(pool)
+            .await?;
+        if !taken {
+            return Ok(candidate);
+        }
+        candidate = format!("{}-{}", base, &auth::generate_token()[..6].to_lowercase());
+    }
+}
+
+/// Start an OpenID Connect login
+///
+/// Redirects the browser to the identity provider using the authorization
+/// code flow with PKCE. A cookie ties the request to this browser, so the
+/// callback must be completed in the same browser.
+#[utoipa::path(
+    get,
+    path = "/oidc/login",
+    params(
+        OidcLoginParams
+    ),
+    responses(
+        (status = 303, description = "Redirect to the identity provider"),
+        (status = 404, description = "OpenID Connect is not configured", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "Identity provider error", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn oidc_login_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Query(params): Query<OidcLoginParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (url, cookie) = start_authorization(&pool, None, params.use_cookies).await?;
+    Ok((AppendHeaders([cookie]), Redirect::to(&url)))
+}
+
+/// Start linking an external identity to the current account
+///
+/// Returns the provider URL to open in the browser; the callback then links
+/// the identity instead of creating a new account. The response sets a cookie
+/// that the callback requires, so the URL only works in the browser that
+/// made this request.
+#[utoipa::path(
+    post,
+    path = "/oidc/link",
+    responses(
+        (status = 200, description = "Provider URL to visit", body = OidcAuthorization),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Requires an interactive login", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "OpenID Connect is not configured", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "Identity provider error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn oidc_link_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_session()?;
+
+    let user_id = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = $1")
+        .bind(&auth_user.username)
+        .fetch_one(&pool)
+        .await?;
+
+    let (authorization_url, cookie) = start_authorization(&pool, Some(user_id), false).await?;
+    Ok((AppendHeaders([cookie]), Json(OidcAuthorization { authorization_url })))
+}
+
+/// Complete an OpenID Connect login
+///
+/// Exchanges the authorization code, verifies the ID token, then logs in the
+/// linked account, links the identity to the account that started the flow,
+/// or provisions a new account on first login.
+#[utoipa::path(
+    get,
+    path = "/oidc/callback",
+    params(
+        OidcCallbackParams
+    ),
+    responses(
+        (status = 200, description = "Login successful", body = TokenResponse),
+        (status = 303, description = "Redirect to the frontend with tokens in the fragment, or with session cookies set"),
+        (status = 400, description = "Unknown or expired state, state from another browser, or provider returned an error", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Account disabled or password reset required", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "OpenID Connect is not configured", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Identity is already linked to another account", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "Identity provider error", body = ProblemDetails, content_type = "application/problem+json")
+    )
+)]
+pub async fn oidc_callback_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    headers: HeaderMap,
+    Query(params): Query<OidcCallbackParams>,
+) -> Response {
+    // The state cookie is only good for one attempt, whatever the outcome.
+    let result = complete_authorization(&pool, &headers, params).await;
+    (AppendHeaders([session::clear_oidc_state_cookie()]), result).into_response()
+}
+
+async fn complete_authorization(
+    pool: &Pool<Postgres>,
+    headers: &HeaderMap,
+    params: OidcCallbackParams,
+) -> Result<Response, ApiError> {
+    let config = config()?;
+
+    if let Some(error) = params.error {
+        return Err(ApiError::BadRequest(format!("Identity provider returned an error: {}", error)));
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return Err(ApiError::BadRequest("Missing code or state".to_string()));
+    };
+    // Without this, an attacker could get a victim's browser to finish a flow
+    // the attacker started, logging the victim in as the attacker or linking
+    // the victim's identity to the attacker's account.
+    if !session::check_oidc_state(headers, &state) {
+        return Err(ApiError::BadRequest("Login was not started in this browser".to_string()));
+    }
+
+    // Each state is single use.
+    let (code_verifier, nonce, link_user_id, use_cookies) = sqlx::query_as::<_, (String, String, Option<i32>, bool)>(
+        "DELETE FROM oidc_login_states
+         WHERE state = $1 AND expires_at > NOW()
+         RETURNING code_verifier, nonce, link_user_id, use_cookies"
+    )
+    .bind(&state)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::BadRequest("Unknown or expired login state".to_string()))?;
+
+    let claims = exchange_code(config, &code, &code_verifier, &nonce)
+        .await
+        .map_err(oidc_error)?;
+
+    let linked_user_id = sqlx::query_scalar::<_, i32>(
+        "SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2"
+    )
+    .bind(&config.issuer)
+    .bind(&claims.sub)
+    .fetch_optional(pool)
+    .await?;
+
+    let user_id = match (linked_user_id, link_user_id) {
+        (Some(existing), Some(requested)) if existing != requested => {
+            return Err(ApiError::Conflict("This identity is linked to another account".to_string()));
+        }
+        (Some(existing), _) => existing,
+        (None, Some(requested)) => {
+            sqlx::query(
+                "INSERT INTO user_identities (user_id, issuer, subject, email) VALUES ($1, $2, $3, $4)"
+            )
+            .bind(requested)
+            .bind(&config.issuer)
+            .bind(&claims.sub)
+            .bind(&claims.email)
+            .execute(pool)
+            .await?;
+            requested
+        }
+        (None, None) => {
+            // First login: provision a local account. It gets a random password,
+            // so it can only be used through the provider until one is set.
+            let username = available_username(pool, &claims).await?;
+            let unusable_password = password::hash_password(&auth::generate_token())?;
+
+            let mut tx = pool.begin().await?;
+            let user_id = sqlx::query_scalar::<_, i32>(
+                "INSERT INTO users (username, password, password_set) VALUES ($1, $2, FALSE) RETURNING id"
+            )
+            .bind(&username)
+            .bind(unusable_password)
+            .fetch_one(&mut tx)
+            .await?;
+            sqlx::query(
+                "INSERT INTO user_identities (user_id, issuer, subject, email) VALUES ($1, $2, $3, $4)"
+            )
+            .bind(user_id)
+            .bind(&config.issuer)
+            .bind(&claims.sub)
+            .bind(&claims.email)
+            .execute(&mut tx)
+            .await?;
+            tx.commit().await?;
+            user_id
+        }
+    };
+
+    sqlx::query("UPDATE user_identities SET last_login_at = NOW() WHERE issuer = $1 AND subject = $2")
+        .bind(&config.issuer)
+        .bind(&claims.sub)
+        .execute(pool)
+        .await?;
+
+    let tokens = issue_tokens(pool, user_id, None).await?;
+
+    if use_cookies {
+        let (cookies, csrf_token) = session::session_cookies(&tokens);
+        return match &config.post_login_redirect {
+            Some(url) => Ok((AppendHeaders(cookies), Redirect::to(url)).into_response()),
+            None => Ok((AppendHeaders(cookies), Json(CookieSession { csrf_token })).into_response()),
+        };
+    }
+
+    match &config.post_login_redirect {
+        // The fragment never reaches any server, unlike a query string
+        Some(url) => Ok(Redirect::to(&format!(
+            "{}#token={}&refresh_token={}",
+            url, tokens.token, tokens.refresh_token
+        ))
+        .into_response()),
+        None => Ok(Json(tokens).into_response()),
+    }
+}
@@ -0,0 +1,158 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::Lazy;
+use rand::rngs::OsRng;
+use std::{env, fmt};
+
+// Argon2id parameters, read once from the environment. `main` forces this at
+// startup so a bad value stops the server instead of the first login.
+pub static ARGON2_PARAMS: Lazy<Params> = Lazy::new(|| {
+    params_from_env().expect("invalid Argon2 configuration")
+});
+
+// The defaults follow the OWASP recommendation of 19 MiB memory, 2 iterations
+// and 1 degree of parallelism
+pub fn params_from_env() -> Result<Params> {
+    let read = |name: &str, default: u32| -> Result<u32> {
+        match env::var(name) {
+            Ok(value) => value.parse().with_context(|| format!("{} must be a number", name)),
+            Err(_) => Ok(default),
+        }
+    };
+    Params::new(
+        read("ARGON2_MEMORY_KIB", 19 * 1024)?,
+        read("ARGON2_ITERATIONS", 2)?,
+        read("ARGON2_PARALLELISM", 1)?,
+        None,
+    )
+    .map_err(|err| anyhow!("invalid Argon2 parameters: {}", err))
+}
+
+// Hash checked against when the user does not exist, so that an unknown username
+// takes as long to reject as a wrong password. `main` forces this at startup so
+// the first unknown username is not slower than later ones.
+pub static DUMMY_HASH: Lazy<String> = Lazy::new(|| {
+    hash_password("dummy password").expect("hashing a constant password cannot fail")
+});
+
+#[derive(Debug)]
+pub enum PasswordError {
+    Argon2(argon2::password_hash::Error),
+    Bcrypt(bcrypt::BcryptError),
+    UnknownFormat,
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordError::Argon2(err) => write!(f, "{}", err),
+            PasswordError::Bcrypt(err) => write!(f, "{}", err),
+            PasswordError::UnknownFormat => write!(f, "unknown password hash format"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordError {}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, ARGON2_PARAMS.clone())
+}
+
+// Hash a password with Argon2id and the configured parameters
+pub fn hash_password(password: &str) -> Result<String, PasswordError> {
+    let salt = SaltString::generate(&mut OsRng);
+    argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(PasswordError::Argon2)
+}
+
+// Verify a password against an Argon2 (PHC string) or legacy bcrypt hash
+pub fn verify_password(password: &str, stored_hash: &str) -> Result<bool, PasswordError> {
+    if stored_hash.starts_with("$argon2") {
+        let parsed = PasswordHash::new(stored_hash).map_err(PasswordError::Argon2)?;
+        match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(err) => Err(PasswordError::Argon2(err)),
+        }
+    } else if stored_hash.starts_with("$2") {
+        bcrypt::verify(password, stored_hash).map_err(PasswordError::Bcrypt)
+    } else {
+        Err(PasswordError::UnknownFormat)
+    }
+}
+
+// Whether a stored hash uses an outdated algorithm or different Argon2 parameters
+pub fn needs_rehash(stored_hash: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(stored_hash) else {
+        // bcrypt hashes are not PHC strings
+        return true;
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    match Params::try_from(&parsed) {
+        Ok(params) => {
+            params.m_cost() != ARGON2_PARAMS.m_cost()
+                || params.t_cost() != ARGON2_PARAMS.t_cost()
+                || params.p_cost() != ARGON2_PARAMS.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+// Burn the same amount of work as a real verification and always fail
+pub fn verify_dummy(password: &str) {
+    let _ = verify_password(password, &DUMMY_HASH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_password, needs_rehash, verify_dummy, verify_password, DUMMY_HASH};
+    use argon2::{
+        password_hash::{PasswordHasher, SaltString},
+        Algorithm, Argon2, Params, Version,
+    };
+    use rand::rngs::OsRng;
+
+    fn argon2_hash(algorithm: Algorithm, params: Params) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::new(algorithm, Version::V0x13, params)
+            .hash_password(b"Correct-Horse-42", &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn current_hashes_do_not_need_a_rehash() {
+        let hash = hash_password("Correct-Horse-42").unwrap();
+        assert!(verify_password("Correct-Horse-42", &hash).unwrap());
+        assert!(!verify_password("wrong", &hash).unwrap());
+        assert!(!needs_rehash(&hash));
+    }
+
+    #[test]
+    fn outdated_hashes_need_a_rehash() {
+        let bcrypt_hash = bcrypt::hash("Correct-Horse-42", 4).unwrap();
+        assert!(verify_password("Correct-Horse-42", &bcrypt_hash).unwrap());
+        assert!(needs_rehash(&bcrypt_hash));
+
+        let weaker = argon2_hash(Algorithm::Argon2id, Params::new(8 * 1024, 1, 1, None).unwrap());
+        assert!(verify_password("Correct-Horse-42", &weaker).unwrap());
+        assert!(needs_rehash(&weaker));
+
+        assert!(needs_rehash(&argon2_hash(Algorithm::Argon2i, super::ARGON2_PARAMS.clone())));
+        assert!(needs_rehash("not a hash"));
+    }
+
+    #[test]
+    fn dummy_verification_never_succeeds() {
+        verify_dummy("Correct-Horse-42");
+        assert!(!verify_password("Correct-Horse-42", &DUMMY_HASH).unwrap());
+        assert!(!needs_rehash(&DUMMY_HASH));
+    }
+}
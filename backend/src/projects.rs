@@ -0,0 +1,281 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    middleware,
+    response::IntoResponse,
+    routing::{get, put},
+    Json, Router,
+};
+use sqlx::{Pool, Postgres, Transaction};
+use crate::auth::{self, AuthenticatedUser};
+use crate::db;
+use crate::error::ApiError;
+use crate::extract::{Path, Query};
+use crate::models::{DeleteProjectParams, NewProject, Project, ProjectDeleteMode, UpdateProject};
+use crate::rank;
+use crate::validation::{ValidatedJson, ValidationErrors};
+
+// Columns selected into `models::Project`; queries alias projects as `p`
+pub const PROJECT_COLUMNS: &str = "p.id, p.name, p.created_at,
+     (SELECT COUNT(*) FROM todos t WHERE t.project_id = p.id AND NOT t.completed) AS open_count,
+     (SELECT COUNT(*) FROM todos t WHERE t.project_id = p.id AND t.completed) AS completed_count";
+
+// Routes under /projects, for the authenticated user's own projects
+pub fn router() -> Router {
+    Router::new()
+        .route("/projects", get(list_projects_handler).post(create_project_handler))
+        .route(
+            "/projects/:id",
+            put(update_project_handler).delete(delete_project_handler)
+        )
+        .layer(middleware::from_fn(auth::require_auth))
+}
+
+fn duplicate_name(err: sqlx::Error) -> ApiError {
+    match db::unique_violation(&err) {
+        Some(_) => ApiError::Conflict("A project with this name already exists".to_string()),
+        None => err.into(),
+    }
+}
+
+// Reject a project_id that does not belong to the user, so todos cannot be
+// filed into someone else's project
+pub async fn check_owned(
+    tx: &mut Transaction<'_, Postgres>,
+    username: &str,
+    project_id: i32,
+) -> Result<(), ApiError> {
+    let owned = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (
+             SELECT 1 FROM projects p JOIN users u ON p.user_id = u.id
+             WHERE p.id = $1 AND u.username = $2
+         )"
+    )
+    .bind(project_id)
+    .bind(username)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if owned {
+        Ok(())
+    } else {
+        let mut errors = ValidationErrors::default();
+        errors.add("project_id", format!("project {} does not exist", project_id));
+        Err(ApiError::Validation(errors))
+    }
+}
+
+/// List the current user's projects
+///
+/// Each project comes with the number of open and completed todos in it.
+#[utoipa::path(
+    get,
+    path = "/projects",
+    responses(
+        (status = 200, description = "List of projects", body = [Project]),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn list_projects_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_READ)?;
+
+    let projects = sqlx::query_as::<_, Project>(&format!(
+        "SELECT {}
+         FROM projects p
+         JOIN users u ON p.user_id = u.id
+         WHERE u.username = $1
+         ORDER BY lower(p.name)",
+        PROJECT_COLUMNS
+    ))
+    .bind(&auth_user.username)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(projects))
+}
+
+/// Create a project
+#[utoipa::path(
+    post,
+    path = "/projects",
+    request_body = NewProject,
+    responses(
+        (status = 200, description = "Project created", body = Project),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "A project with this name already exists", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid name", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn create_project_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    ValidatedJson(payload): ValidatedJson<NewProject>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
+    let project = sqlx::query_as::<_, Project>(
+        "INSERT INTO projects (user_id, name)
+         SELECT id, $2 FROM users WHERE username = $1
+         RETURNING id, name, created_at, 0::BIGINT AS open_count, 0::BIGINT AS completed_count"
+    )
+    .bind(&auth_user.username)
+    .bind(payload.name.trim())
+    .fetch_one(&pool)
+    .await
+    .map_err(duplicate_name)?;
+
+    Ok(Json(project))
+}
+
+/// Rename a project
+#[utoipa::path(
+    put,
+    path = "/projects/{id}",
+    params(
+        ("id" = i32, Path, description = "Project ID")
+    ),
+    request_body = UpdateProject,
+    responses(
+        (status = 200, description = "Project updated", body = Project),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Project not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "A project with this name already exists", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid name", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn update_project_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateProject>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
+    let project = sqlx::query_as::<_, Project>(&format!(
+        "UPDATE projects p
+         SET name = COALESCE($1, p.name)
+         FROM users u
+         WHERE p.id = $2 AND p.user_id = u.id AND u.username = $3
+         RETURNING {}",
+        PROJECT_COLUMNS
+    ))
+    .bind(payload.name.as_deref().map(str::trim))
+    .bind(id)
+    .bind(&auth_user.username)
+    .fetch_optional(&pool)
+    .await
+    .map_err(duplicate_name)?
+    .ok_or_else(|| ApiError::NotFound(format!("Project with id {} not found", id)))?;
+
+    Ok(Json(project))
+}
+
+/// Delete a project
+///
+/// By default its todos are moved to the end of the inbox (no project); with
+/// `mode=cascade` they are deleted along with it.
+#[utoipa::path(
+    delete,
+    path = "/projects/{id}",
+    params(
+        ("id" = i32, Path, description = "Project ID"),
+        DeleteProjectParams
+    ),
+    responses(
+        (status = 204, description = "Project deleted"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Project not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn delete_project_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+    Query(params): Query<DeleteProjectParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
+    let mut tx = pool.begin().await?;
+
+    // Deleting the project would move its todos to the inbox through the
+    // foreign key, so with cascade they have to go first. Everything happens in
+    // one transaction, which is rolled back if the project turns out not to exist.
+    if params.mode.unwrap_or(ProjectDeleteMode::Inbox) == ProjectDeleteMode::Cascade {
+        sqlx::query(
+            "DELETE FROM todos t
+             USING projects p, users u
+             WHERE t.project_id = $1 AND p.id = t.project_id
+             AND p.user_id = u.id AND u.username = $2"
+        )
+        .bind(id)
+        .bind(&auth_user.username)
+        .execute(&mut tx)
+        .await?;
+    } else {
+        // Like a todo moved out of a project, each one goes to the end of its
+        // new list. Taking them in order keeps their order there.
+        let todos = sqlx::query_as::<_, (i32, i32, Option<i32>)>(
+            "SELECT t.id, t.user_id, t.parent_id FROM todos t
+             JOIN projects p ON p.id = t.project_id
+             JOIN users u ON u.id = p.user_id
+             WHERE t.project_id = $1 AND u.username = $2
+             ORDER BY t.position, t.id"
+        )
+        .bind(id)
+        .bind(&auth_user.username)
+        .fetch_all(&mut tx)
+        .await?;
+
+        for (todo_id, user_id, parent_id) in todos {
+            let position = rank::append_position(&mut tx, user_id, None, parent_id).await?;
+            sqlx::query("UPDATE todos SET project_id = NULL, position = $1 WHERE id = $2")
+                .bind(&position)
+                .bind(todo_id)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+
+    let result = sqlx::query(
+        "DELETE FROM projects p
+         USING users u
+         WHERE p.id = $1 AND p.user_id = u.id AND u.username = $2"
+    )
+    .bind(id)
+    .bind(&auth_user.username)
+    .execute(&mut tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound(format!("Project with id {} not found", id)));
+    }
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
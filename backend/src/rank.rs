@@ -0,0 +1,119 @@
+use sqlx::{Postgres, Transaction};
+
+// Positions are strings over these digits, compared bytewise (the column uses
+// the "C" collation). A new position can always be found between two others,
+// so moving a todo never rewrites its neighbours.
+const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+const BASE: usize = DIGITS.len();
+
+fn digit(key: &[u8], i: usize) -> usize {
+    key.get(i)
+        .and_then(|c| DIGITS.iter().position(|d| d == c))
+        .unwrap_or(0)
+}
+
+// A position strictly between `lower` and `upper`, where None means the start
+// or end of the list. Results never end in '0', which keeps room below them.
+pub fn between(lower: Option<&str>, upper: Option<&str>) -> String {
+    let lower = lower.unwrap_or_default().as_bytes();
+    let mut upper = upper.map(str::as_bytes);
+    let mut key = Vec::new();
+
+    for i in 0.. {
+        let low = digit(lower, i);
+        let high = match upper {
+            Some(upper) if i < upper.len() => digit(upper, i),
+            _ => BASE,
+        };
+        if low == high {
+            key.push(DIGITS[low]);
+            continue;
+        }
+        let mid = (low + high) / 2;
+        key.push(DIGITS[mid]);
+        if mid > low {
+            break;
+        }
+        // The prefix is now below `upper`, so any continuation is too
+        upper = None;
+    }
+    String::from_utf8(key).expect("positions are ASCII")
+}
+
+// Serialize position changes per user so that two todos appended or moved at
+// the same time do not end up with the same position
+pub async fn lock_positions(tx: &mut Transaction<'_, Postgres>, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('todo_positions'), $1)")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// A position after every todo in the given list
+pub async fn append_position(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    project_id: Option<i32>,
+    parent_id: Option<i32>,
+) -> Result<String, sqlx::Error> {
+    lock_positions(tx, user_id).await?;
+    let last = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT MAX(position) FROM todos
+         WHERE user_id = $1
+         AND project_id IS NOT DISTINCT FROM $2
+         AND parent_id IS NOT DISTINCT FROM $3"
+    )
+    .bind(user_id)
+    .bind(project_id)
+    .bind(parent_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(between(last.as_deref(), None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::between;
+
+    #[test]
+    fn finds_a_key_strictly_between() {
+        let cases = [
+            (None, None),
+            (None, Some("i")),
+            (Some("i"), None),
+            (Some("a"), Some("b")),
+            (Some("0000000009"), Some("0000000010")),
+            (Some("az"), Some("b")),
+            (None, Some("01")),
+        ];
+        for (lower, upper) in cases {
+            let key = between(lower, upper);
+            if let Some(lower) = lower {
+                assert!(lower < key.as_str(), "{} < {}", lower, key);
+            }
+            if let Some(upper) = upper {
+                assert!(key.as_str() < upper, "{} < {}", key, upper);
+            }
+            assert!(!key.ends_with('0'));
+        }
+    }
+
+    #[test]
+    fn repeated_inserts_stay_ordered() {
+        let mut lower = "i".to_string();
+        let upper = "j".to_string();
+        for _ in 0..200 {
+            let key = between(Some(&lower), Some(&upper));
+            assert!(lower < key && key < upper);
+            lower = key;
+        }
+        let mut upper = "i".to_string();
+        for _ in 0..200 {
+            let key = between(None, Some(&upper));
+            assert!(key < upper);
+            upper = key;
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+// Periods searched for the next occurrence before giving up, so that rules
+// that can never match (BYMONTHDAY=30 with BYMONTH=2) cannot loop forever
+const MAX_EMPTY_PERIODS: u32 = 1000;
+
+// Largest INTERVAL accepted; anything bigger would step past the dates chrono
+// can represent within a few periods
+const MAX_INTERVAL: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+// A weekday in BYDAY, optionally with an ordinal such as the 2 in 2TU
+// ("second Tuesday") or the -1 in -1FR ("last Friday")
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByDay {
+    pub ordinal: Option<i32>,
+    pub weekday: Weekday,
+}
+
+// The subset of RFC 5545 RRULE supported for todos: FREQ, INTERVAL, COUNT,
+// UNTIL, BYDAY, BYMONTHDAY and BYMONTH
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<DateTime<Utc>>,
+    pub by_day: Vec<ByDay>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+}
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon),
+    ("TU", Weekday::Tue),
+    ("WE", Weekday::Wed),
+    ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri),
+    ("SA", Weekday::Sat),
+    ("SU", Weekday::Sun),
+];
+
+fn parse_list<T>(value: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Vec<T>, String> {
+    value
+        .split(',')
+        .map(|item| parse(item).ok_or_else(|| format!("invalid value '{}'", item)))
+        .collect()
+}
+
+fn parse_by_day(item: &str) -> Option<ByDay> {
+    let split = item.len().checked_sub(2)?;
+    let (ordinal, day) = (item.get(..split)?, item.get(split..)?);
+    let weekday = WEEKDAYS.iter().find(|(name, _)| *name == day)?.1;
+    let ordinal = match ordinal {
+        "" => None,
+        ordinal => Some(ordinal.parse::<i32>().ok().filter(|n| *n != 0 && n.abs() <= 53)?),
+    };
+    Some(ByDay { ordinal, weekday })
+}
+
+fn parse_until(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date_time) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ") {
+        return Some(Utc.from_utc_datetime(&date_time));
+    }
+    // A plain date includes the whole day
+    let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(23, 59, 59)?))
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Rule, String> {
+        let value = value.trim();
+        let value = value
+            .strip_prefix("RRULE:")
+            .or_else(|| value.strip_prefix("rrule:"))
+            .unwrap_or(value);
+
+        let mut frequency = None;
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+        };
+
+        for part in value.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not of the form NAME=VALUE", part))?;
+            let value = value.to_ascii_uppercase();
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("FREQ={} is not supported", value)),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| (1..=MAX_INTERVAL).contains(interval))
+                        .ok_or_else(|| format!("INTERVAL must be a number from 1 to {}", MAX_INTERVAL))?
+                }
+                "COUNT" => {
+                    rule.count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|count| *count >= 1)
+                            .ok_or("COUNT must be a positive number")?,
+                    )
+                }
+                "UNTIL" => rule.until = Some(parse_until(&value).ok_or("UNTIL must be a date such as 20251231 or 20251231T120000Z")?),
+                "BYDAY" => rule.by_day = parse_list(&value, parse_by_day).map_err(|err| format!("BYDAY: {}", err))?,
+                "BYMONTHDAY" => {
+                    rule.by_month_day = parse_list(&value, |item| {
+                        item.parse::<i32>().ok().filter(|day| *day != 0 && day.abs() <= 31)
+                    })
+                    .map_err(|err| format!("BYMONTHDAY: {}", err))?
+                }
+                "BYMONTH" => {
+                    rule.by_month = parse_list(&value, |item| {
+                        item.parse::<u32>().ok().filter(|month| (1..=12).contains(month))
+                    })
+                    .map_err(|err| format!("BYMONTH: {}", err))?
+                }
+                // The week always starts on Monday, which is the RFC default
+                "WKST" if value == "MO" => {}
+                name => return Err(format!("{} is not supported", name)),
+            }
+        }
+
+        rule.frequency = frequency.ok_or("FREQ is required")?;
+        if rule.count.is_some() && rule.until.is_some() {
+            return Err("COUNT and UNTIL cannot be combined".to_string());
+        }
+        let ordinals = rule.by_day.iter().any(|day| day.ordinal.is_some());
+        if ordinals && !matches!(rule.frequency, Frequency::Monthly | Frequency::Yearly) {
+            return Err("BYDAY ordinals such as 1MO need FREQ=MONTHLY or FREQ=YEARLY".to_string());
+        }
+        if !rule.by_month_day.is_empty() && rule.frequency == Frequency::Weekly {
+            return Err("BYMONTHDAY cannot be used with FREQ=WEEKLY".to_string());
+        }
+        Ok(rule)
+    }
+}
+
+// Canonical RRULE text, as stored in todos.recurrence
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", frequency)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"))?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|day| {
+                    let name = WEEKDAYS.iter().find(|(_, weekday)| *weekday == day.weekday).unwrap().0;
+                    match day.ordinal {
+                        Some(ordinal) => format!("{}{}", ordinal, name),
+                        None => name.to_string(),
+                    }
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(i32::to_string).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if !self.by_month.is_empty() {
+            let months: Vec<String> = self.by_month.iter().map(u32::to_string).collect();
+            write!(f, ";BYMONTH={}", months.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .and_then(|first| first.pred_opt())
+        .map_or(31, |last| last.day())
+}
+
+// Days of the given month selected by BYMONTHDAY and BYDAY, or `default_day`
+// when neither is set
+fn month_days(rule: &Rule, year: i32, month: u32, default_day: u32) -> Vec<NaiveDate> {
+    if NaiveDate::from_ymd_opt(year, month, 1).is_none() {
+        return Vec::new();
+    }
+    let length = days_in_month(year, month) as i32;
+    let all = (1..=length as u32).filter_map(|day| NaiveDate::from_ymd_opt(year, month, day));
+
+    let matches_month_day = |date: &NaiveDate| {
+        rule.by_month_day.is_empty()
+            || rule.by_month_day.iter().any(|&day| {
+                let day = if day < 0 { length + day + 1 } else { day };
+                date.day() as i32 == day
+            })
+    };
+    let matches_by_day = |date: &NaiveDate| {
+        rule.by_day.is_empty()
+            || rule.by_day.iter().any(|by_day| {
+                if date.weekday() != by_day.weekday {
+                    return false;
+                }
+                match by_day.ordinal {
+                    None => true,
+                    Some(ordinal) if ordinal > 0 => (date.day() as i32 - 1) / 7 + 1 == ordinal,
+                    Some(ordinal) => (length - date.day() as i32) / 7 + 1 == -ordinal,
+                }
+            })
+    };
+
+    if rule.by_month_day.is_empty() && rule.by_day.is_empty() {
+        return NaiveDate::from_ymd_opt(year, month, default_day).into_iter().collect();
+    }
+    all.filter(|date| matches_month_day(date) && matches_by_day(date)).collect()
+}
+
+// Candidate dates of the `index`-th period after the one containing `start`.
+// A period beyond the dates chrono can represent has none.
+fn period_dates(rule: &Rule, start: NaiveDate, index: u32) -> Vec<NaiveDate> {
+    let step = index as i64 * rule.interval as i64;
+    let in_months = |date: &NaiveDate| rule.by_month.is_empty() || rule.by_month.contains(&date.month());
+    match rule.frequency {
+        Frequency::Daily => {
+            let Some(date) = Duration::try_days(step).and_then(|offset| start.checked_add_signed(offset)) else {
+                return Vec::new();
+            };
+            let weekday_ok = rule.by_day.is_empty() || rule.by_day.iter().any(|day| day.weekday == date.weekday());
+            let month_day_ok = rule.by_month_day.is_empty()
+                || month_days(rule, date.year(), date.month(), date.day()).contains(&date);
+            if weekday_ok && month_day_ok && in_months(&date) {
+                vec![date]
+            } else {
+                Vec::new()
+            }
+        }
+        Frequency::Weekly => {
+            let monday = Duration::try_weeks(step)
+                .and_then(|offset| start.checked_add_signed(offset))
+                .and_then(|date| date.checked_sub_signed(Duration::days(date.weekday().num_days_from_monday() as i64)));
+            let Some(monday) = monday else {
+                return Vec::new();
+            };
+            let weekdays: Vec<Weekday> = if rule.by_day.is_empty() {
+                vec![start.weekday()]
+            } else {
+                rule.by_day.iter().map(|day| day.weekday).collect()
+            };
+            let mut dates: Vec<NaiveDate> = (0..7)
+                .filter_map(|offset| monday.checked_add_signed(Duration::days(offset)))
+                .filter(|date| weekdays.contains(&date.weekday()) && in_months(date))
+                .collect();
+            dates.sort();
+            dates
+        }
+        Frequency::Monthly => {
+            let months = start.year() as i64 * 12 + start.month0() as i64 + step;
+            let Ok(year) = i32::try_from(months / 12) else {
+                return Vec::new();
+            };
+            let month = (months % 12) as u32 + 1;
+            if !rule.by_month.is_empty() && !rule.by_month.contains(&month) {
+                return Vec::new();
+            }
+            month_days(rule, year, month, start.day())
+        }
+        Frequency::Yearly => {
+            let Some(year) = i32::try_from(step).ok().and_then(|step| start.year().checked_add(step)) else {
+                return Vec::new();
+            };
+            let months = if rule.by_month.is_empty() { vec![start.month()] } else { rule.by_month.clone() };
+            let mut dates: Vec<NaiveDate> = months
+                .into_iter()
+                .flat_map(|month| month_days(rule, year, month, start.day()))
+                .collect();
+            dates.sort();
+            dates
+        }
+    }
+}
+
+// Local wall-clock time to an instant; times skipped by a DST change move
+// forward by an hour
+fn to_utc(tz: Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(local + Duration::hours(1))).earliest())
+        .map(|date_time| date_time.with_timezone(&Utc))
+}
+
+// Up to `limit` occurrences strictly after `after`, for a series whose first
+// occurrence is `start`. Dates and times of day are evaluated in `tz`, so a
+// todo due at 08:00 stays at 08:00 local time across DST changes. COUNT
+// counts from `start`.
+pub fn occurrences_after(
+    rule: &Rule,
+    start: DateTime<Utc>,
+    tz: Tz,
+    after: DateTime<Utc>,
+    limit: usize,
+) -> Vec<DateTime<Utc>> {
+    let local_start = start.with_timezone(&tz).naive_local();
+    let mut found = Vec::new();
+    let mut seen = 0;
+    let mut empty_periods = 0;
+
+    for index in 0.. {
+        let mut matched = false;
+        for date in period_dates(rule, local_start.date(), index) {
+            let Some(occurrence) = to_utc(tz, date.and_time(local_start.time())) else {
+                continue;
+            };
+            if occurrence < start {
+                continue;
+            }
+            if rule.until.is_some_and(|until| occurrence > until) {
+                return found;
+            }
+            matched = true;
+            seen += 1;
+            if rule.count.is_some_and(|count| seen > count) {
+                return found;
+            }
+            if occurrence > after {
+                found.push(occurrence);
+                if found.len() >= limit {
+                    return found;
+                }
+            }
+        }
+        empty_periods = if matched { 0 } else { empty_periods + 1 };
+        if empty_periods >= MAX_EMPTY_PERIODS {
+            break;
+        }
+    }
+    found
+}
+
+// The user's time zone, falling back to UTC for names chrono-tz does not know
+pub fn time_zone(name: &str) -> Tz {
+    name.parse().unwrap_or(Tz::UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{occurrences_after, period_dates, time_zone, Rule, MAX_INTERVAL};
+    use chrono::{DateTime, Utc};
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    fn next(rule: &str, start: &str, zone: &str, count: usize) -> Vec<String> {
+        let rule: Rule = rule.parse().unwrap();
+        occurrences_after(&rule, utc(start), time_zone(zone), utc(start), count)
+            .iter()
+            .map(|occurrence| occurrence.to_rfc3339())
+            .collect()
+    }
+
+    #[test]
+    fn parses_and_prints_canonical_rules() {
+        let rule: Rule = "RRULE:freq=weekly;byday=tu,th;interval=2".parse().unwrap();
+        assert_eq!(rule.to_string(), "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH");
+        assert!("FREQ=HOURLY".parse::<Rule>().is_err());
+        assert!("BYDAY=MO".parse::<Rule>().is_err());
+        assert!("FREQ=WEEKLY;BYDAY=1MO".parse::<Rule>().is_err());
+        assert!("FREQ=DAILY;COUNT=2;UNTIL=20250101".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn weekly_on_tuesdays() {
+        // 2025-10-14 is a Tuesday
+        assert_eq!(
+            next("FREQ=WEEKLY;BYDAY=TU", "2025-10-14T18:00:00+00:00", "UTC", 2),
+            ["2025-10-21T18:00:00+00:00", "2025-10-28T18:00:00+00:00"]
+        );
+    }
+
+    #[test]
+    fn keeps_local_time_across_dst() {
+        // Europe/Berlin leaves summer time on 2025-10-26
+        assert_eq!(
+            next("FREQ=DAILY", "2025-10-25T06:00:00+00:00", "Europe/Berlin", 2),
+            ["2025-10-26T07:00:00+00:00", "2025-10-27T07:00:00+00:00"]
+        );
+    }
+
+    #[test]
+    fn monthly_by_day_of_month_skips_short_months() {
+        assert_eq!(
+            next("FREQ=MONTHLY;BYMONTHDAY=31", "2025-01-31T09:00:00+00:00", "UTC", 2),
+            ["2025-03-31T09:00:00+00:00", "2025-05-31T09:00:00+00:00"]
+        );
+        assert_eq!(
+            next("FREQ=MONTHLY;BYDAY=-1FR", "2025-10-31T09:00:00+00:00", "UTC", 1),
+            ["2025-11-28T09:00:00+00:00"]
+        );
+    }
+
+    #[test]
+    fn stops_at_count_and_until() {
+        assert_eq!(next("FREQ=DAILY;COUNT=3", "2025-10-01T09:00:00+00:00", "UTC", 10).len(), 2);
+        assert_eq!(next("FREQ=DAILY;UNTIL=20251003", "2025-10-01T09:00:00+00:00", "UTC", 10).len(), 2);
+        assert!(next("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30", "2025-01-01T00:00:00+00:00", "UTC", 1).is_empty());
+    }
+
+    #[test]
+    fn huge_intervals_are_rejected_and_never_overflow() {
+        assert!("FREQ=DAILY;INTERVAL=4000000000".parse::<Rule>().is_err());
+        assert!(format!("FREQ=DAILY;INTERVAL={}", MAX_INTERVAL + 1).parse::<Rule>().is_err());
+
+        let start = utc("2025-10-01T09:00:00+00:00");
+        for frequency in ["DAILY", "WEEKLY", "MONTHLY", "YEARLY"] {
+            let rule: Rule = format!("FREQ={};INTERVAL={}", frequency, MAX_INTERVAL).parse().unwrap();
+            assert!(period_dates(&rule, start.date_naive(), u32::MAX).is_empty(), "{}", frequency);
+        }
+
+        // Yearly steps leave chrono's range after a few hundred periods
+        let rule: Rule = format!("FREQ=YEARLY;INTERVAL={}", MAX_INTERVAL).parse().unwrap();
+        let occurrences = occurrences_after(&rule, start, time_zone("UTC"), start, usize::MAX);
+        assert!(!occurrences.is_empty() && occurrences.len() < 1000);
+    }
+}
@@ -0,0 +1,124 @@
+use axum::http::{header, HeaderMap, HeaderName, Method};
+use cookie::{time::Duration, Cookie, SameSite};
+use once_cell::sync::Lazy;
+use std::env;
+use crate::auth::{self, REFRESH_TOKEN_TTL_DAYS};
+use crate::error::ApiError;
+use crate::models::TokenResponse;
+use crate::totp::constant_time_eq;
+
+// Cookie names used by the browser session mode
+pub const SESSION_COOKIE: &str = "session";
+pub const REFRESH_COOKIE: &str = "refresh_token";
+pub const CSRF_COOKIE: &str = "csrf_token";
+// Header the client echoes the CSRF cookie in (double-submit pattern)
+pub const CSRF_HEADER: &str = "x-csrf-token";
+// Hash of the OIDC state, binding an authorization request to the browser that started it
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+// Cookies are marked Secure unless COOKIE_SECURE=false (for plain-http development)
+static COOKIE_SECURE: Lazy<bool> = Lazy::new(|| {
+    env::var("COOKIE_SECURE").map(|v| v != "false").unwrap_or(true)
+});
+
+// Value of a cookie sent by the client
+pub fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+        .find(|cookie| cookie.name() == name)
+        .map(|cookie| cookie.value().to_string())
+}
+
+// The CSRF header must be present and match the CSRF cookie, compared in
+// constant time so the token cannot be guessed byte by byte
+pub fn check_csrf(headers: &HeaderMap) -> Result<(), ApiError> {
+    let cookie = cookie_value(headers, CSRF_COOKIE);
+    let header = headers.get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    let matches = match (cookie, header) {
+        (Some(cookie), Some(header)) => !cookie.is_empty() && constant_time_eq(cookie.as_bytes(), header.as_bytes()),
+        _ => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden("Missing or invalid CSRF token".to_string()))
+    }
+}
+
+// The token presented with a request: a Bearer header wins, otherwise the session
+// cookie, which needs a matching CSRF token on anything but safe methods
+pub fn request_token(method: &Method, headers: &HeaderMap) -> Result<String, ApiError> {
+    if let Some(token) = auth::bearer_token(headers) {
+        return Ok(token.to_string());
+    }
+    let token = cookie_value(headers, SESSION_COOKIE)
+        .ok_or_else(|| ApiError::Unauthorized("Missing credentials".to_string()))?;
+    if !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        check_csrf(headers)?;
+    }
+    Ok(token)
+}
+
+fn build(name: &'static str, value: String, http_only: bool, max_age: Duration) -> (HeaderName, String) {
+    let cookie = Cookie::build(name, value)
+        .path("/")
+        .http_only(http_only)
+        .secure(*COOKIE_SECURE)
+        .same_site(SameSite::Strict)
+        .max_age(max_age)
+        .finish();
+    (header::SET_COOKIE, cookie.to_string())
+}
+
+// Set-Cookie headers for a new session, plus the CSRF token the client must echo
+pub fn session_cookies(tokens: &TokenResponse) -> (Vec<(HeaderName, String)>, String) {
+    let csrf_token = auth::generate_token();
+    let refresh_max_age = Duration::days(REFRESH_TOKEN_TTL_DAYS);
+    let cookies = vec![
+        build(SESSION_COOKIE, tokens.token.clone(), true, Duration::hours(1)),
+        build(REFRESH_COOKIE, tokens.refresh_token.clone(), true, refresh_max_age),
+        // Readable by scripts so it can be copied into the CSRF header
+        build(CSRF_COOKIE, csrf_token.clone(), false, refresh_max_age),
+    ];
+    (cookies, csrf_token)
+}
+
+// Set-Cookie headers that remove the session cookies
+pub fn clear_session_cookies() -> Vec<(HeaderName, String)> {
+    vec![
+        build(SESSION_COOKIE, String::new(), true, Duration::ZERO),
+        build(REFRESH_COOKIE, String::new(), true, Duration::ZERO),
+        build(CSRF_COOKIE, String::new(), false, Duration::ZERO),
+    ]
+}
+
+fn build_oidc_state(value: String, max_age: Duration) -> (HeaderName, String) {
+    let cookie = Cookie::build(OIDC_STATE_COOKIE, value)
+        .path("/")
+        .http_only(true)
+        .secure(*COOKIE_SECURE)
+        .same_site(SameSite::Lax)
+        .max_age(max_age)
+        .finish();
+    (header::SET_COOKIE, cookie.to_string())
+}
+
+// Set-Cookie header binding an OIDC authorization request to this browser. It
+// has to be Lax, since the provider sends the browser back with a cross-site
+// redirect, and lives as long as the request may take to complete.
+pub fn oidc_state_cookie(state: &str, max_age: Duration) -> (HeaderName, String) {
+    build_oidc_state(auth::hash_token(state), max_age)
+}
+
+pub fn clear_oidc_state_cookie() -> (HeaderName, String) {
+    build_oidc_state(String::new(), Duration::ZERO)
+}
+
+// Whether the browser presenting `state` is the one that started the request
+pub fn check_oidc_state(headers: &HeaderMap, state: &str) -> bool {
+    cookie_value(headers, OIDC_STATE_COOKIE).is_some_and(|hash| hash == auth::hash_token(state))
+}
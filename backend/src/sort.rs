@@ -0,0 +1,99 @@
+use crate::validation::ValidationErrors;
+
+// Todo priorities from lowest to highest; stored as text in todos.priority
+pub const PRIORITIES: [&str; 5] = ["none", "low", "medium", "high", "urgent"];
+
+// Keys accepted in `?sort=`, mapped to the SQL they order by. Only these
+// expressions ever reach the query; the client's text is never interpolated.
+const SORT_KEYS: &[(&str, &str)] = &[
+    ("position", "t.position"),
+    (
+        "priority",
+        "CASE t.priority WHEN 'low' THEN 1 WHEN 'medium' THEN 2 WHEN 'high' THEN 3 WHEN 'urgent' THEN 4 ELSE 0 END",
+    ),
+    ("due_at", "t.due_at"),
+    ("start_at", "t.start_at"),
+    ("created_at", "t.created_at"),
+    ("updated_at", "t.updated_at"),
+    ("completed_at", "t.completed_at"),
+    ("title", "lower(t.title)"),
+    ("completed", "t.completed"),
+    ("id", "t.id"),
+];
+
+pub const MAX_SORT_KEYS: usize = 5;
+
+// Turn a list like `-priority,due_at` into an ORDER BY clause. A leading `-`
+// sorts descending; todos without a value always come last. Without keys the
+// manual order is used. The id is appended so that pages of equal rows come
+// back in a stable order.
+pub fn order_by(sort: Option<&str>) -> Result<String, ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+    let mut terms = Vec::new();
+    let mut used = Vec::new();
+
+    let keys = sort
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty());
+    for key in keys {
+        let (name, direction) = match key.strip_prefix('-') {
+            Some(name) => (name, "DESC"),
+            None => (key.strip_prefix('+').unwrap_or(key), "ASC"),
+        };
+        match SORT_KEYS.iter().find(|(allowed, _)| *allowed == name) {
+            Some((_, expression)) if !used.contains(&name) => {
+                used.push(name);
+                terms.push(format!("{} {} NULLS LAST", expression, direction));
+            }
+            Some(_) => errors.add("sort", format!("'{}' is given more than once", name)),
+            None => errors.add(
+                "sort",
+                format!(
+                    "unknown key '{}'; expected one of {}",
+                    name,
+                    SORT_KEYS.iter().map(|(allowed, _)| *allowed).collect::<Vec<_>>().join(", ")
+                ),
+            ),
+        }
+    }
+    if used.len() > MAX_SORT_KEYS {
+        errors.add("sort", format!("must have at most {} keys", MAX_SORT_KEYS));
+    }
+    errors.into_result()?;
+
+    if terms.is_empty() {
+        terms.push("t.position ASC".to_string());
+    }
+    if !used.contains(&"id") {
+        terms.push("t.id ASC".to_string());
+    }
+    Ok(format!(" ORDER BY {}", terms.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::order_by;
+
+    #[test]
+    fn defaults_to_manual_order() {
+        assert_eq!(order_by(None).unwrap(), " ORDER BY t.position ASC, t.id ASC");
+        assert_eq!(order_by(Some("")).unwrap(), " ORDER BY t.position ASC, t.id ASC");
+    }
+
+    #[test]
+    fn accepts_multiple_keys_with_direction() {
+        let clause = order_by(Some("-priority, due_at")).unwrap();
+        assert!(clause.starts_with(" ORDER BY CASE t.priority"));
+        assert!(clause.contains("END DESC NULLS LAST, t.due_at ASC NULLS LAST, t.id ASC"));
+    }
+
+    #[test]
+    fn rejects_keys_outside_the_whitelist() {
+        let errors = order_by(Some("due_at,title;DROP TABLE todos")).unwrap_err();
+        assert_eq!(errors.fields()["sort"].len(), 1);
+        assert!(order_by(Some("-user_id")).is_err());
+        assert!(order_by(Some("due_at,-due_at")).is_err());
+    }
+}
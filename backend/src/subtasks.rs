@@ -0,0 +1,231 @@
+use sqlx::{Postgres, Transaction};
+use std::collections::HashMap;
+use crate::error::ApiError;
+use crate::handlers::TODO_COLUMNS;
+use crate::models::Todo;
+use crate::validation::ValidationErrors;
+
+// A top-level todo is at depth 1; subtasks may nest down to this depth
+pub const MAX_DEPTH: i32 = 5;
+
+// Serialize tree changes per user. Without it two concurrent updates moving
+// A below B and B below A could both pass check_parent and store a cycle.
+async fn lock_tree(tx: &mut Transaction<'_, Postgres>, username: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('todo_tree'), id) FROM users WHERE username = $1")
+        .bind(username)
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+fn invalid_parent(message: impl Into<String>) -> ApiError {
+    let mut errors = ValidationErrors::default();
+    errors.add("parent_id", message);
+    ApiError::Validation(errors)
+}
+
+// Check that `parent_id` can hold `todo_id` (None for a todo being created):
+// the parent must belong to the user, must not lie inside the todo's own
+// subtree, and the moved subtree must stay within MAX_DEPTH. Takes the
+// user's tree lock, which is held until the transaction ends.
+pub async fn check_parent(
+    tx: &mut Transaction<'_, Postgres>,
+    username: &str,
+    todo_id: Option<i32>,
+    parent_id: i32,
+) -> Result<(), ApiError> {
+    lock_tree(tx, username).await?;
+
+    // The parent and its ancestors, nearest first. The depth guard keeps the
+    // walk finite even if a cycle was ever stored.
+    let ancestors = sqlx::query_scalar::<_, i32>(
+        "WITH RECURSIVE up AS (
+             SELECT t.id, t.parent_id, 1 AS depth
+             FROM todos t JOIN users u ON t.user_id = u.id
+             WHERE t.id = $1 AND u.username = $2
+             UNION ALL
+             SELECT t.id, t.parent_id, up.depth + 1
+             FROM todos t JOIN up ON t.id = up.parent_id
+             WHERE up.depth <= $3
+         )
+         SELECT id FROM up ORDER BY depth"
+    )
+    .bind(parent_id)
+    .bind(username)
+    .bind(MAX_DEPTH)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if ancestors.is_empty() {
+        return Err(invalid_parent(format!("todo {} does not exist", parent_id)));
+    }
+    if todo_id.is_some_and(|id| ancestors.contains(&id)) {
+        return Err(invalid_parent("a todo cannot be moved below itself or one of its subtasks"));
+    }
+
+    // Levels in the subtree being attached, counting the todo itself
+    let height = match todo_id {
+        Some(id) => sqlx::query_scalar::<_, i32>(
+            "WITH RECURSIVE down AS (
+                 SELECT id, 1 AS level FROM todos WHERE id = $1
+                 UNION ALL
+                 SELECT t.id, down.level + 1
+                 FROM todos t JOIN down ON t.parent_id = down.id
+                 WHERE down.level <= $2
+             )
+             SELECT MAX(level) FROM down"
+        )
+        .bind(id)
+        .bind(MAX_DEPTH)
+        .fetch_one(&mut *tx)
+        .await?,
+        None => 1,
+    };
+
+    if ancestors.len() as i32 + height > MAX_DEPTH {
+        return Err(invalid_parent(format!("subtasks may be nested at most {} levels deep", MAX_DEPTH)));
+    }
+    Ok(())
+}
+
+// Apply the user's completion rules after `todo_id` was created, moved or
+// had `completed` changed. `old_parent_id` is the parent it was moved away
+// from, whose completion may depend on it no longer being there.
+pub async fn apply_completion_rules(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    todo_id: i32,
+    old_parent_id: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    let (complete_children, auto_complete_parents) = sqlx::query_as::<_, (bool, bool)>(
+        "SELECT complete_children_with_parent, auto_complete_parents FROM users WHERE id = $1"
+    )
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let (completed, parent_id) = sqlx::query_as::<_, (bool, Option<i32>)>(
+        "SELECT completed, parent_id FROM todos WHERE id = $1"
+    )
+    .bind(todo_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if complete_children && completed {
+        sqlx::query(
+            "WITH RECURSIVE down AS (
+                 SELECT id, 1 AS level FROM todos WHERE parent_id = $1
+                 UNION ALL
+                 SELECT t.id, down.level + 1
+                 FROM todos t JOIN down ON t.parent_id = down.id
+                 WHERE down.level < $2
+             )
+             UPDATE todos t
+             SET completed = TRUE, completed_at = NOW(), updated_at = NOW()
+             FROM down
+             WHERE t.id = down.id AND NOT t.completed"
+        )
+        .bind(todo_id)
+        .bind(MAX_DEPTH)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    if auto_complete_parents {
+        for start in [parent_id, old_parent_id.filter(|old| Some(*old) != parent_id)] {
+            sync_ancestors(tx, start).await?;
+        }
+    }
+    Ok(())
+}
+
+// Apply the parent rules after a subtask of `parent_id` was removed
+pub async fn parent_changed(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    parent_id: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    let auto_complete_parents = sqlx::query_scalar::<_, bool>(
+        "SELECT auto_complete_parents FROM users WHERE id = $1"
+    )
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if auto_complete_parents {
+        sync_ancestors(tx, parent_id).await?;
+    }
+    Ok(())
+}
+
+// Walk up from `parent_id`, marking each todo completed exactly when all of
+// its subtasks are, and stop at the first one that does not change. A chain
+// is never longer than MAX_DEPTH, so neither is the walk.
+async fn sync_ancestors(
+    tx: &mut Transaction<'_, Postgres>,
+    mut parent_id: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    for _ in 0..MAX_DEPTH {
+        let Some(id) = parent_id else { break };
+        parent_id = sqlx::query_scalar::<_, Option<i32>>(
+            "UPDATE todos p
+             SET completed = sub.all_done,
+                 completed_at = CASE WHEN sub.all_done THEN NOW() END,
+                 updated_at = NOW()
+             FROM (SELECT COALESCE(bool_and(c.completed), p2.completed) AS all_done
+                   FROM todos p2 LEFT JOIN todos c ON c.parent_id = p2.id
+                   WHERE p2.id = $1
+                   GROUP BY p2.completed) sub
+             WHERE p.id = $1 AND p.completed <> sub.all_done
+             RETURNING p.parent_id"
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .flatten();
+    }
+    Ok(())
+}
+
+// Load every descendant of `roots` and attach them as nested `children`
+pub async fn attach_children(
+    tx: &mut Transaction<'_, Postgres>,
+    roots: &mut [Todo],
+) -> Result<(), sqlx::Error> {
+    let ids: Vec<i32> = roots.iter().map(|todo| todo.id).collect();
+    let descendants = sqlx::query_as::<_, Todo>(&format!(
+        "WITH RECURSIVE down AS (
+             SELECT id, 1 AS level FROM todos WHERE parent_id = ANY($1)
+             UNION ALL
+             SELECT t.id, down.level + 1
+             FROM todos t JOIN down ON t.parent_id = down.id
+             WHERE down.level < $2
+         )
+         SELECT {} FROM todos t JOIN down ON down.id = t.id
+         ORDER BY t.position, t.id",
+        TODO_COLUMNS
+    ))
+    .bind(&ids)
+    .bind(MAX_DEPTH)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut by_parent: HashMap<i32, Vec<Todo>> = HashMap::new();
+    for todo in descendants {
+        if let Some(parent_id) = todo.parent_id {
+            by_parent.entry(parent_id).or_default().push(todo);
+        }
+    }
+    for root in roots.iter_mut() {
+        build(root, &mut by_parent);
+    }
+    Ok(())
+}
+
+fn build(todo: &mut Todo, by_parent: &mut HashMap<i32, Vec<Todo>>) {
+    let mut children = by_parent.remove(&todo.id).unwrap_or_default();
+    for child in children.iter_mut() {
+        build(child, by_parent);
+    }
+    todo.children = Some(children);
+}
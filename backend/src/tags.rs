@@ -0,0 +1,256 @@
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    middleware,
+    response::IntoResponse,
+    routing::{get, put},
+    Json, Router,
+};
+use sqlx::{Pool, Postgres, Transaction};
+use crate::auth::{self, AuthenticatedUser};
+use crate::db;
+use crate::error::ApiError;
+use crate::extract::Path;
+use crate::models::{NewTag, Tag, UpdateTag};
+use crate::validation::ValidatedJson;
+
+// Routes under /tags, for the authenticated user's own tags
+pub fn router() -> Router {
+    Router::new()
+        .route("/tags", get(list_tags_handler).post(create_tag_handler))
+        .route("/tags/:id", put(update_tag_handler).delete(delete_tag_handler))
+        .layer(middleware::from_fn(auth::require_auth))
+}
+
+fn duplicate_name(err: sqlx::Error) -> ApiError {
+    match db::unique_violation(&err) {
+        Some(_) => ApiError::Conflict("A tag with this name already exists".to_string()),
+        None => err.into(),
+    }
+}
+
+// Trimmed names with case-insensitive duplicates removed, keeping the first spelling
+pub fn normalize_names(names: &[String]) -> Vec<String> {
+    let mut seen = Vec::new();
+    let mut unique = Vec::new();
+    for name in names.iter().map(|name| name.trim()) {
+        let folded = name.to_lowercase();
+        if !seen.contains(&folded) {
+            seen.push(folded);
+            unique.push(name.to_string());
+        }
+    }
+    unique
+}
+
+// Replace the tags of a todo, creating tags the user does not have yet.
+// Returns the todo's tag names as stored.
+pub async fn set_todo_tags(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    todo_id: i32,
+    names: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    let names = normalize_names(names);
+    let folded: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+
+    sqlx::query(
+        "INSERT INTO tags (user_id, name)
+         SELECT $1, name FROM UNNEST($2::text[]) AS name
+         ON CONFLICT DO NOTHING"
+    )
+    .bind(user_id)
+    .bind(&names)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM todo_tags WHERE todo_id = $1")
+        .bind(todo_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query_scalar::<_, String>(
+        "WITH linked AS (
+             INSERT INTO todo_tags (todo_id, tag_id)
+             SELECT $1, id FROM tags WHERE user_id = $2 AND lower(name) = ANY($3)
+             RETURNING tag_id
+         )
+         SELECT tg.name FROM linked JOIN tags tg ON tg.id = linked.tag_id
+         ORDER BY lower(tg.name)"
+    )
+    .bind(todo_id)
+    .bind(user_id)
+    .bind(&folded)
+    .fetch_all(&mut *tx)
+    .await
+}
+
+/// List the current user's tags
+#[utoipa::path(
+    get,
+    path = "/tags",
+    responses(
+        (status = 200, description = "List of tags", body = [Tag]),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn list_tags_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_READ)?;
+
+    let tags = sqlx::query_as::<_, Tag>(
+        "SELECT tg.id, tg.name, tg.color,
+                (SELECT COUNT(*) FROM todo_tags tt WHERE tt.tag_id = tg.id) AS todo_count
+         FROM tags tg
+         JOIN users u ON tg.user_id = u.id
+         WHERE u.username = $1
+         ORDER BY lower(tg.name)"
+    )
+    .bind(&auth_user.username)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(tags))
+}
+
+/// Create a tag
+#[utoipa::path(
+    post,
+    path = "/tags",
+    request_body = NewTag,
+    responses(
+        (status = 200, description = "Tag created", body = Tag),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "A tag with this name already exists", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid name or color", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn create_tag_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    ValidatedJson(payload): ValidatedJson<NewTag>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
+    let tag = sqlx::query_as::<_, Tag>(
+        "INSERT INTO tags (user_id, name, color)
+         SELECT id, $2, COALESCE($3, '#9e9e9e') FROM users WHERE username = $1
+         RETURNING id, name, color, 0::BIGINT AS todo_count"
+    )
+    .bind(&auth_user.username)
+    .bind(payload.name.trim())
+    .bind(payload.color.as_deref().map(str::to_lowercase))
+    .fetch_one(&pool)
+    .await
+    .map_err(duplicate_name)?;
+
+    Ok(Json(tag))
+}
+
+/// Rename or recolor a tag
+#[utoipa::path(
+    put,
+    path = "/tags/{id}",
+    params(
+        ("id" = i32, Path, description = "Tag ID")
+    ),
+    request_body = UpdateTag,
+    responses(
+        (status = 200, description = "Tag updated", body = Tag),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Tag not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "A tag with this name already exists", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid name or color", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn update_tag_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateTag>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
+    let tag = sqlx::query_as::<_, Tag>(
+        "UPDATE tags tg
+         SET name = COALESCE($1, tg.name),
+             color = COALESCE($2, tg.color)
+         FROM users u
+         WHERE tg.id = $3 AND tg.user_id = u.id AND u.username = $4
+         RETURNING tg.id, tg.name, tg.color,
+                   (SELECT COUNT(*) FROM todo_tags tt WHERE tt.tag_id = tg.id) AS todo_count"
+    )
+    .bind(payload.name.as_deref().map(str::trim))
+    .bind(payload.color.as_deref().map(str::to_lowercase))
+    .bind(id)
+    .bind(&auth_user.username)
+    .fetch_optional(&pool)
+    .await
+    .map_err(duplicate_name)?
+    .ok_or_else(|| ApiError::NotFound(format!("Tag with id {} not found", id)))?;
+
+    Ok(Json(tag))
+}
+
+/// Delete a tag
+///
+/// The tag is removed from all todos; the todos themselves are kept.
+#[utoipa::path(
+    delete,
+    path = "/tags/{id}",
+    params(
+        ("id" = i32, Path, description = "Tag ID")
+    ),
+    responses(
+        (status = 204, description = "Tag deleted"),
+        (status = 401, description = "Unauthorized", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "Missing scope or unverified email address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Tag not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "Internal server error", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn delete_tag_handler(
+    Extension(pool): Extension<Pool<Postgres>>,
+    Extension(auth_user): Extension<AuthenticatedUser>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    auth_user.require_scope(auth::SCOPE_TODOS_WRITE)?;
+    auth_user.require_verified_email()?;
+
+    let result = sqlx::query(
+        "DELETE FROM tags tg
+         USING users u
+         WHERE tg.id = $1 AND tg.user_id = u.id AND u.username = $2"
+    )
+    .bind(id)
+    .bind(&auth_user.username)
+    .execute(&pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound(format!("Tag with id {} not found", id)));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,189 @@
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use sqlx::{Pool, Postgres};
+use std::{env, net::SocketAddr};
+
+// How many failures are tolerated before lockouts start, and how they grow
+pub struct Policy {
+    pub free_attempts: i32,
+    pub base_delay_secs: i64,
+    pub max_delay_secs: i64,
+}
+
+// Per account: after 5 failures lock for 30s, doubling up to 15 minutes
+pub const ACCOUNT_POLICY: Policy = Policy {
+    free_attempts: 5,
+    base_delay_secs: 30,
+    max_delay_secs: 15 * 60,
+};
+
+// Per IP: more lenient, since many users can share an address
+pub const IP_POLICY: Policy = Policy {
+    free_attempts: 20,
+    base_delay_secs: 60,
+    max_delay_secs: 60 * 60,
+};
+
+// Password reset requests: a few per account, more per IP, then back off.
+// Counted separately from login failures so requesting a link cannot lock
+// anyone out of logging in.
+pub const RESET_ACCOUNT_POLICY: Policy = Policy {
+    free_attempts: 3,
+    base_delay_secs: 60,
+    max_delay_secs: 60 * 60,
+};
+
+pub const RESET_IP_POLICY: Policy = Policy {
+    free_attempts: 10,
+    base_delay_secs: 60,
+    max_delay_secs: 60 * 60,
+};
+
+pub fn account_key(username: &str) -> String {
+    format!("user:{}", username.to_lowercase())
+}
+
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+pub fn reset_account_key(username: &str) -> String {
+    format!("reset:user:{}", username.to_lowercase())
+}
+
+pub fn reset_ip_key(ip: &str) -> String {
+    format!("reset:ip:{}", ip)
+}
+
+// Number of proxies in front of the server whose X-Forwarded-For entries are
+// trusted, from TRUST_PROXY_HEADERS=true and TRUSTED_PROXY_HOPS (default 1).
+// Zero when proxy headers are not trusted.
+static TRUSTED_PROXY_HOPS: Lazy<usize> = Lazy::new(|| {
+    let trust_proxy = env::var("TRUST_PROXY_HEADERS").map(|v| v == "true").unwrap_or(false);
+    if !trust_proxy {
+        return 0;
+    }
+    env::var("TRUSTED_PROXY_HOPS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1)
+});
+
+// The entry of an X-Forwarded-For header added by the outermost trusted proxy.
+// Each proxy appends the address it received the request from, so only the
+// right-most `hops` entries are trustworthy; anything left of them is whatever
+// the client chose to send.
+fn forwarded_client(header: &str, hops: usize) -> Option<&str> {
+    let entries: Vec<&str> = header
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    let index = entries.len().saturating_sub(hops);
+    entries.get(index).copied()
+}
+
+// The client address, taken from X-Forwarded-For when the server runs behind
+// trusted proxies (e.g. a load balancer) and from the TCP connection otherwise
+pub fn client_ip(headers: &HeaderMap, addr: &SocketAddr) -> String {
+    let hops = *TRUSTED_PROXY_HOPS;
+    if hops > 0 {
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| forwarded_client(value, hops));
+        if let Some(ip) = forwarded {
+            return ip.to_string();
+        }
+    }
+    addr.ip().to_string()
+}
+
+// Seconds until any of the keys is unlocked, or `None` if none is locked
+pub async fn locked_for(pool: &Pool<Postgres>, keys: &[String]) -> Result<Option<i64>, sqlx::Error> {
+    let locked_until = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        "SELECT MAX(locked_until) FROM login_attempts
+         WHERE key = ANY($1) AND locked_until > NOW()"
+    )
+    .bind(keys)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(locked_until.map(|until| (until - Utc::now()).num_seconds().max(1)))
+}
+
+// Count a failure and lock the key with exponential backoff once past the free attempts.
+// Failures older than a day are forgotten, and their rows removed once unlocked.
+pub async fn record_failure(pool: &Pool<Postgres>, key: &str, policy: &Policy) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM login_attempts
+         WHERE last_failure_at < NOW() - INTERVAL '1 day'
+         AND (locked_until IS NULL OR locked_until < NOW())"
+    )
+    .execute(pool)
+    .await?;
+
+    let failures = sqlx::query_scalar::<_, i32>(
+        "INSERT INTO login_attempts (key, failures, last_failure_at)
+         VALUES ($1, 1, NOW())
+         ON CONFLICT (key) DO UPDATE SET
+             failures = CASE
+                 WHEN login_attempts.last_failure_at < NOW() - INTERVAL '1 day' THEN 1
+                 ELSE login_attempts.failures + 1
+             END,
+             last_failure_at = NOW()
+         RETURNING failures"
+    )
+    .bind(key)
+    .fetch_one(pool)
+    .await?;
+
+    if failures >= policy.free_attempts {
+        let exponent = (failures - policy.free_attempts).min(16) as u32;
+        let delay = policy
+            .base_delay_secs
+            .saturating_mul(2i64.pow(exponent))
+            .min(policy.max_delay_secs);
+        sqlx::query(
+            "UPDATE login_attempts SET locked_until = NOW() + make_interval(secs => $2)
+             WHERE key = $1"
+        )
+        .bind(key)
+        .bind(delay as f64)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+// Forget failures after a successful login
+pub async fn reset(pool: &Pool<Postgres>, key: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_attempts WHERE key = $1")
+        .bind(key)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::forwarded_client;
+
+    #[test]
+    fn uses_the_entry_added_by_the_outermost_trusted_proxy() {
+        assert_eq!(forwarded_client("203.0.113.7", 1), Some("203.0.113.7"));
+        assert_eq!(forwarded_client("1.2.3.4, 203.0.113.7", 1), Some("203.0.113.7"));
+        assert_eq!(forwarded_client("1.2.3.4, 203.0.113.7, 10.0.0.2", 2), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn ignores_forged_and_empty_entries() {
+        // A client cannot pick its address by prepending entries
+        assert_eq!(forwarded_client("9.9.9.9, 8.8.8.8, 203.0.113.7", 1), Some("203.0.113.7"));
+        assert_eq!(forwarded_client("203.0.113.7, ", 1), Some("203.0.113.7"));
+        // Fewer entries than hops: every entry came from a trusted proxy
+        assert_eq!(forwarded_client("203.0.113.7", 3), Some("203.0.113.7"));
+        assert_eq!(forwarded_client(" , ", 1), None);
+    }
+}
@@ -0,0 +1,203 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// RFC 6238 defaults, which is what authenticator apps expect
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+// Accept codes from one step before or after the current one to allow for clock drift
+const ALLOWED_DRIFT: i64 = 1;
+
+pub const ISSUER: &str = "Todo App";
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+// Generate a new 160-bit secret, base32 encoded as authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+// Build the otpauth:// URI that authenticator apps read from a QR code
+pub fn otpauth_uri(username: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(ISSUER),
+        percent_encode(username),
+        secret,
+        percent_encode(ISSUER),
+        DIGITS,
+        STEP_SECONDS,
+    )
+}
+
+// HOTP value (RFC 4226) for the given counter
+fn code_at(secret: &[u8], step: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS)
+}
+
+fn current_step() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    (now / STEP_SECONDS) as i64
+}
+
+// Compare two byte strings without returning early on the first difference
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// Check a code against the secret and return the time step it matched.
+// Steps at or before `last_used_step` are rejected so a code cannot be replayed.
+pub fn verify_code(secret: &str, code: &str, last_used_step: Option<i64>) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    verify_code_at(&secret, code, current_step(), last_used_step)
+}
+
+fn verify_code_at(secret: &[u8], code: &str, now: i64, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    (now - ALLOWED_DRIFT..=now + ALLOWED_DRIFT)
+        .filter(|step| !matches!(last_used_step, Some(last) if *step <= last))
+        .find(|step| {
+            let expected = format!("{:0width$}", code_at(secret, *step as u64), width = DIGITS as usize);
+            constant_time_eq(expected.as_bytes(), code.as_bytes())
+        })
+}
+
+// One-time recovery codes in the form `xxxxx-xxxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let raw: String = (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &raw[..5], &raw[5..])
+        })
+        .collect()
+}
+
+// Recovery codes are compared case-insensitively and without the dash
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim()
+        .chars()
+        .filter(|c| *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+// The id of the unused recovery code whose hash matches. Every stored hash is
+// compared in full so the time taken does not depend on which one matched.
+pub fn find_recovery_code(code_hash: &str, codes: &[(i32, String, bool)]) -> Option<i32> {
+    codes.iter().fold(None, |found, (id, hash, used)| {
+        let matches = constant_time_eq(hash.as_bytes(), code_hash.as_bytes());
+        if matches && !used && found.is_none() {
+            Some(*id)
+        } else {
+            found
+        }
+    })
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{code_at, find_recovery_code, normalize_recovery_code, verify_code_at, STEP_SECONDS};
+
+    // The shared secret used by the test vectors in RFC 4226 and RFC 6238
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn matches_rfc4226_hotp_vectors() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(code_at(SECRET, counter as u64), *code, "counter {}", counter);
+        }
+    }
+
+    #[test]
+    fn matches_rfc6238_sha1_vectors() {
+        // The RFC lists 8-digit codes; 6-digit codes are their last 6 digits
+        let expected = [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ];
+        for (time, code) in expected {
+            assert_eq!(code_at(SECRET, time / STEP_SECONDS), code, "time {}", time);
+        }
+    }
+
+    fn code(step: i64) -> String {
+        format!("{:06}", code_at(SECRET, step as u64))
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift_either_way() {
+        let now = 1000;
+        assert_eq!(verify_code_at(SECRET, &code(now), now, None), Some(now));
+        assert_eq!(verify_code_at(SECRET, &code(now - 1), now, None), Some(now - 1));
+        assert_eq!(verify_code_at(SECRET, &code(now + 1), now, None), Some(now + 1));
+        assert_eq!(verify_code_at(SECRET, &code(now - 2), now, None), None);
+        assert_eq!(verify_code_at(SECRET, &code(now + 2), now, None), None);
+    }
+
+    #[test]
+    fn rejects_replayed_and_malformed_codes() {
+        let now = 1000;
+        assert_eq!(verify_code_at(SECRET, &code(now), now, Some(now)), None);
+        assert_eq!(verify_code_at(SECRET, &code(now - 1), now, Some(now - 1)), None);
+        assert_eq!(verify_code_at(SECRET, &code(now + 1), now, Some(now)), Some(now + 1));
+        assert_eq!(verify_code_at(SECRET, &format!(" {} ", code(now)), now, None), Some(now));
+        assert_eq!(verify_code_at(SECRET, &code(now)[..5], now, None), None);
+        assert_eq!(verify_code_at(SECRET, "abcdef", now, None), None);
+    }
+
+    #[test]
+    fn recovery_codes_are_single_use() {
+        let hash = normalize_recovery_code("ABCDE-fghij");
+        assert_eq!(hash, "abcdefghij");
+        let codes = vec![(1, "other".to_string(), false), (2, hash.clone(), false)];
+        assert_eq!(find_recovery_code(&hash, &codes), Some(2));
+
+        let used = vec![(1, "other".to_string(), false), (2, hash.clone(), true)];
+        assert_eq!(find_recovery_code(&hash, &used), None);
+        assert_eq!(find_recovery_code("unknown", &codes), None);
+    }
+}
@@ -0,0 +1,593 @@
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::FromRequest,
+    http::Request,
+    BoxError,
+};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use crate::error::ApiError;
+use crate::extract::JsonBody;
+use crate::recurrence::Rule;
+use crate::sort::PRIORITIES;
+use crate::models::{ChangePasswordPayload, MoveTodoPayload, NewProject, NewTag, NewTodo, RegisterPayload, ResetPasswordPayload, UpdateProfilePayload, UpdateProject, UpdateTag, UpdateTodo};
+
+pub const USERNAME_MIN_LEN: usize = 3;
+pub const USERNAME_MAX_LEN: usize = 32;
+pub const PASSWORD_MIN_LEN: usize = 10;
+pub const PASSWORD_MAX_LEN: usize = 128;
+// Passwords at least this long are accepted without mixing character classes
+pub const PASSPHRASE_MIN_LEN: usize = 16;
+pub const EMAIL_MAX_LEN: usize = 254;
+pub const TITLE_MAX_LEN: usize = 200;
+pub const NOTES_MAX_LEN: usize = 20_000;
+pub const TAG_NAME_MAX_LEN: usize = 50;
+pub const TAGS_PER_TODO_MAX: usize = 20;
+pub const PROJECT_NAME_MAX_LEN: usize = 100;
+pub const TIMEZONE_MAX_LEN: usize = 64;
+
+// Messages per field, rendered as a 422 response by `ApiError::Validation`
+#[derive(Debug, Default)]
+pub struct ValidationErrors(BTreeMap<&'static str, Vec<String>>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.0.entry(field).or_default().push(message.into());
+    }
+
+    pub fn fields(&self) -> BTreeMap<String, Vec<String>> {
+        self.0
+            .iter()
+            .map(|(field, messages)| (field.to_string(), messages.clone()))
+            .collect()
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+// Like `Json<T>`, but rejects payloads that fail `T::validate` with 422 and
+// renders malformed bodies as problem details too
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let JsonBody(value) = JsonBody::<T>::from_request(req, state).await?;
+        value.validate()?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+fn check_username(errors: &mut ValidationErrors, username: &str) {
+    let len = username.chars().count();
+    if !(USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&len) {
+        errors.add(
+            "username",
+            format!("must be {} to {} characters long", USERNAME_MIN_LEN, USERNAME_MAX_LEN),
+        );
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        errors.add("username", "may only contain letters, digits, '_', '.' and '-'");
+    }
+}
+
+fn check_password(errors: &mut ValidationErrors, field: &'static str, password: &str) {
+    let len = password.chars().count();
+    if len < PASSWORD_MIN_LEN {
+        errors.add(field, format!("must be at least {} characters long", PASSWORD_MIN_LEN));
+        return;
+    }
+    if len > PASSWORD_MAX_LEN {
+        errors.add(field, format!("must be at most {} characters long", PASSWORD_MAX_LEN));
+        return;
+    }
+
+    // Shorter passwords must mix character classes; long passphrases need not.
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if len < PASSPHRASE_MIN_LEN && classes.iter().filter(|&&present| present).count() < 3 {
+        errors.add(
+            field,
+            format!(
+                "must contain three of lowercase letters, uppercase letters, digits and symbols, or be at least {} characters long",
+                PASSPHRASE_MIN_LEN
+            ),
+        );
+    }
+}
+
+fn check_email(errors: &mut ValidationErrors, email: &str) {
+    let email = email.trim();
+    if email.len() > EMAIL_MAX_LEN {
+        errors.add("email", format!("must be at most {} characters long", EMAIL_MAX_LEN));
+    }
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        None => false,
+    };
+    if !valid {
+        errors.add("email", "must be a valid email address");
+    }
+}
+
+fn check_title(errors: &mut ValidationErrors, title: &str) {
+    let title = title.trim();
+    if title.is_empty() {
+        errors.add("title", "must not be empty");
+    }
+    if title.chars().count() > TITLE_MAX_LEN {
+        errors.add("title", format!("must be at most {} characters long", TITLE_MAX_LEN));
+    }
+    if title.chars().any(char::is_control) {
+        errors.add("title", "must not contain control characters");
+    }
+}
+
+impl Validate for RegisterPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_username(&mut errors, &self.username);
+        check_password(&mut errors, "password", &self.password);
+        if self.password.eq_ignore_ascii_case(&self.username) {
+            errors.add("password", "must not be the same as the username");
+        }
+        if let Some(email) = self.email.as_deref().filter(|email| !email.trim().is_empty()) {
+            check_email(&mut errors, email);
+        }
+        errors.into_result()
+    }
+}
+
+fn check_schedule(errors: &mut ValidationErrors, start_at: Option<DateTime<Utc>>, due_at: Option<DateTime<Utc>>) {
+    if let (Some(start_at), Some(due_at)) = (start_at, due_at) {
+        if start_at > due_at {
+            errors.add("start_at", "must not be after due_at");
+        }
+    }
+}
+
+fn check_notes(errors: &mut ValidationErrors, notes: &str) {
+    if notes.chars().count() > NOTES_MAX_LEN {
+        errors.add("notes", format!("must be at most {} characters long", NOTES_MAX_LEN));
+    }
+}
+
+fn check_tag_name(errors: &mut ValidationErrors, field: &'static str, name: &str) {
+    let name = name.trim();
+    if name.is_empty() {
+        errors.add(field, "tag names must not be empty");
+    }
+    if name.chars().count() > TAG_NAME_MAX_LEN {
+        errors.add(field, format!("tag names must be at most {} characters long", TAG_NAME_MAX_LEN));
+    }
+    if name.chars().any(|c| c.is_control() || c == ',') {
+        errors.add(field, "tag names must not contain commas or control characters");
+    }
+}
+
+fn check_tags(errors: &mut ValidationErrors, tags: &[String]) {
+    if tags.len() > TAGS_PER_TODO_MAX {
+        errors.add("tags", format!("must have at most {} entries", TAGS_PER_TODO_MAX));
+    }
+    for tag in tags {
+        check_tag_name(errors, "tags", tag);
+    }
+}
+
+fn check_color(errors: &mut ValidationErrors, color: &str) {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        errors.add("color", "must be a hex color like #ff9800");
+    }
+}
+
+fn check_project_name(errors: &mut ValidationErrors, name: &str) {
+    let name = name.trim();
+    if name.is_empty() {
+        errors.add("name", "must not be empty");
+    }
+    if name.chars().count() > PROJECT_NAME_MAX_LEN {
+        errors.add("name", format!("must be at most {} characters long", PROJECT_NAME_MAX_LEN));
+    }
+    if name.chars().any(char::is_control) {
+        errors.add("name", "must not contain control characters");
+    }
+}
+
+fn check_recurrence(errors: &mut ValidationErrors, recurrence: &str) {
+    if let Err(message) = recurrence.parse::<Rule>() {
+        errors.add("recurrence", message);
+    }
+}
+
+fn check_priority(errors: &mut ValidationErrors, priority: &str) {
+    if !PRIORITIES.contains(&priority) {
+        errors.add("priority", format!("must be one of {}", PRIORITIES.join(", ")));
+    }
+}
+
+impl Validate for NewTodo {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_title(&mut errors, &self.title);
+        check_schedule(&mut errors, self.start_at, self.due_at);
+        if let Some(priority) = &self.priority {
+            check_priority(&mut errors, priority);
+        }
+        if let Some(notes) = &self.notes {
+            check_notes(&mut errors, notes);
+        }
+        check_tags(&mut errors, &self.tags);
+        if let Some(recurrence) = &self.recurrence {
+            check_recurrence(&mut errors, recurrence);
+            if self.due_at.is_none() {
+                errors.add("due_at", "is required for recurring todos");
+            }
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateTodo {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(title) = &self.title {
+            check_title(&mut errors, title);
+        }
+        // Only the pair given here can be compared; the database checks the rest
+        check_schedule(&mut errors, self.start_at.flatten(), self.due_at.flatten());
+        if let Some(priority) = &self.priority {
+            check_priority(&mut errors, priority);
+        }
+        if let Some(Some(notes)) = &self.notes {
+            check_notes(&mut errors, notes);
+        }
+        if let Some(tags) = &self.tags {
+            check_tags(&mut errors, tags);
+        }
+        if let Some(Some(recurrence)) = &self.recurrence {
+            check_recurrence(&mut errors, recurrence);
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for MoveTodoPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        match (self.before, self.after) {
+            (None, None) => errors.add("before", "either before or after is required"),
+            (Some(before), Some(after)) if before == after => {
+                errors.add("after", "must differ from before")
+            }
+            _ => {}
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for NewTag {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_tag_name(&mut errors, "name", &self.name);
+        if let Some(color) = &self.color {
+            check_color(&mut errors, color);
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateTag {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(name) = &self.name {
+            check_tag_name(&mut errors, "name", name);
+        }
+        if let Some(color) = &self.color {
+            check_color(&mut errors, color);
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for NewProject {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_project_name(&mut errors, &self.name);
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateProject {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(name) = &self.name {
+            check_project_name(&mut errors, name);
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for UpdateProfilePayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        // Whether the zone exists is checked against the database's zone list
+        if let Some(timezone) = &self.timezone {
+            if timezone.is_empty() || timezone.len() > TIMEZONE_MAX_LEN {
+                errors.add("timezone", format!("must be 1 to {} characters long", TIMEZONE_MAX_LEN));
+            }
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for ChangePasswordPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_password(&mut errors, "new_password", &self.new_password);
+        errors.into_result()
+    }
+}
+
+impl Validate for ResetPasswordPayload {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_password(&mut errors, "new_password", &self.new_password);
+        errors.into_result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Validate, ValidationErrors, PASSWORD_MAX_LEN, TAGS_PER_TODO_MAX, TAG_NAME_MAX_LEN, TITLE_MAX_LEN, USERNAME_MAX_LEN};
+    use crate::error::ApiError;
+    use crate::handlers::duplicate_user;
+    use crate::models::{MoveTodoPayload, NewTag, NewTodo, RegisterPayload, UpdateTodo};
+    use axum::http::StatusCode;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+    use sqlx::error::DatabaseError;
+    use std::{borrow::Cow, error::Error, fmt};
+
+    // Fields with errors, or an empty list if the payload is valid
+    fn invalid<T: DeserializeOwned + Validate>(payload: Value) -> Vec<String> {
+        let payload: T = serde_json::from_value(payload).unwrap();
+        match payload.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.fields().into_keys().collect(),
+        }
+    }
+
+    fn register(username: &str, password: &str) -> Vec<String> {
+        invalid::<RegisterPayload>(json!({ "username": username, "password": password }))
+    }
+
+    fn new_todo(payload: Value) -> Vec<String> {
+        invalid::<NewTodo>(payload)
+    }
+
+    #[test]
+    fn usernames() {
+        assert!(register("abc", "Correct-Horse-42").is_empty());
+        assert!(register(&"a".repeat(USERNAME_MAX_LEN), "Correct-Horse-42").is_empty());
+        assert!(register("john.doe_1-x", "Correct-Horse-42").is_empty());
+        assert_eq!(register("ab", "Correct-Horse-42"), ["username"]);
+        assert_eq!(register(&"a".repeat(USERNAME_MAX_LEN + 1), "Correct-Horse-42"), ["username"]);
+        assert_eq!(register("john doe", "Correct-Horse-42"), ["username"]);
+        assert_eq!(register("jöhn", "Correct-Horse-42"), ["username"]);
+    }
+
+    #[test]
+    fn password_policy() {
+        // Ten characters from three classes
+        assert!(register("john", "Abcdefgh1x").is_empty());
+        assert_eq!(register("john", "Abcdefg1x"), ["password"]);
+        // Two classes are only enough for a passphrase
+        assert_eq!(register("john", "abcdefgh12"), ["password"]);
+        assert_eq!(register("john", "abcdefghijklmno"), ["password"]);
+        assert!(register("john", "abcdefghijklmnop").is_empty());
+        assert!(register("john", &"a".repeat(PASSWORD_MAX_LEN)).is_empty());
+        assert_eq!(register("john", &"a".repeat(PASSWORD_MAX_LEN + 1)), ["password"]);
+        assert_eq!(register("correct-horse-42", "Correct-Horse-42"), ["password"]);
+    }
+
+    #[test]
+    fn emails() {
+        let email = |email: &str| {
+            invalid::<RegisterPayload>(json!({
+                "username": "john", "password": "Correct-Horse-42", "email": email
+            }))
+        };
+        assert!(email("john@example.com").is_empty());
+        assert!(email(" john@example.com ").is_empty());
+        // A blank address is treated as no address
+        assert!(email("  ").is_empty());
+        assert_eq!(email("john.example.com"), ["email"]);
+        assert_eq!(email("@example.com"), ["email"]);
+        assert_eq!(email("john@localhost"), ["email"]);
+        assert_eq!(email("john@.example.com"), ["email"]);
+        assert_eq!(email("john@example.com."), ["email"]);
+        assert_eq!(email("john@ex@ample.com"), ["email"]);
+        assert_eq!(email("jo hn@example.com"), ["email"]);
+        assert!(email(&format!("{}@example.com", "a".repeat(242))).is_empty());
+        assert_eq!(email(&format!("{}@example.com", "a".repeat(243))), ["email"]);
+    }
+
+    #[test]
+    fn titles() {
+        assert!(new_todo(json!({ "title": "a".repeat(TITLE_MAX_LEN) })).is_empty());
+        assert!(new_todo(json!({ "title": format!("  {}  ", "a".repeat(TITLE_MAX_LEN)) })).is_empty());
+        assert_eq!(new_todo(json!({ "title": "a".repeat(TITLE_MAX_LEN + 1) })), ["title"]);
+        assert_eq!(new_todo(json!({ "title": "   " })), ["title"]);
+        assert_eq!(new_todo(json!({ "title": "Buy\nmilk" })), ["title"]);
+        assert!(invalid::<UpdateTodo>(json!({})).is_empty());
+        assert_eq!(invalid::<UpdateTodo>(json!({ "title": "" })), ["title"]);
+    }
+
+    #[test]
+    fn schedule() {
+        let schedule = |start_at: &str, due_at: &str| {
+            new_todo(json!({ "title": "Report", "start_at": start_at, "due_at": due_at }))
+        };
+        assert!(schedule("2025-10-20T09:00:00Z", "2025-10-20T09:00:00Z").is_empty());
+        assert_eq!(schedule("2025-10-20T09:00:01Z", "2025-10-20T09:00:00Z"), ["start_at"]);
+    }
+
+    #[test]
+    fn priorities() {
+        assert!(new_todo(json!({ "title": "Report", "priority": "urgent" })).is_empty());
+        assert_eq!(new_todo(json!({ "title": "Report", "priority": "URGENT" })), ["priority"]);
+    }
+
+    #[test]
+    fn tags() {
+        let tags = |tags: Vec<String>| new_todo(json!({ "title": "Report", "tags": tags }));
+        assert!(tags(vec!["x".to_string(); TAGS_PER_TODO_MAX]).is_empty());
+        assert_eq!(tags(vec!["x".to_string(); TAGS_PER_TODO_MAX + 1]), ["tags"]);
+        assert!(tags(vec!["a".repeat(TAG_NAME_MAX_LEN)]).is_empty());
+        assert_eq!(tags(vec!["a".repeat(TAG_NAME_MAX_LEN + 1)]), ["tags"]);
+        assert_eq!(tags(vec![" ".to_string()]), ["tags"]);
+        assert_eq!(tags(vec!["home,work".to_string()]), ["tags"]);
+    }
+
+    #[test]
+    fn colors() {
+        let color = |color: &str| invalid::<NewTag>(json!({ "name": "errands", "color": color }));
+        assert!(color("#ff9800").is_empty());
+        assert!(color("#FF9800").is_empty());
+        assert_eq!(color("#fff"), ["color"]);
+        assert_eq!(color("ff98000"), ["color"]);
+        assert_eq!(color("#ff98001"), ["color"]);
+        assert_eq!(color("#gg9800"), ["color"]);
+    }
+
+    #[test]
+    fn recurrence() {
+        let due_at = "2025-10-21T09:00:00Z";
+        assert!(new_todo(json!({ "title": "Standup", "due_at": due_at, "recurrence": "FREQ=WEEKLY;BYDAY=TU" })).is_empty());
+        assert_eq!(new_todo(json!({ "title": "Standup", "due_at": due_at, "recurrence": "FREQ=HOURLY" })), ["recurrence"]);
+        assert_eq!(new_todo(json!({ "title": "Standup", "recurrence": "FREQ=DAILY" })), ["due_at"]);
+
+        // null stops a todo from repeating; the due date is checked by the database
+        assert!(invalid::<UpdateTodo>(json!({ "recurrence": null })).is_empty());
+        assert!(invalid::<UpdateTodo>(json!({ "recurrence": "FREQ=DAILY;INTERVAL=2" })).is_empty());
+        assert_eq!(invalid::<UpdateTodo>(json!({ "recurrence": "FREQ=DAILY;INTERVAL=0" })), ["recurrence"]);
+    }
+
+    #[test]
+    fn move_anchors() {
+        assert!(invalid::<MoveTodoPayload>(json!({ "after": 3 })).is_empty());
+        assert!(invalid::<MoveTodoPayload>(json!({ "before": 7, "after": 3 })).is_empty());
+        assert_eq!(invalid::<MoveTodoPayload>(json!({})), ["before"]);
+        assert_eq!(invalid::<MoveTodoPayload>(json!({ "before": 3, "after": 3 })), ["after"]);
+    }
+
+    #[test]
+    fn errors_are_collected_per_field() {
+        let mut errors = ValidationErrors::default();
+        assert!(ValidationErrors::default().into_result().is_ok());
+        errors.add("title", "must not be empty");
+        errors.add("title", "must not contain control characters");
+        assert_eq!(errors.fields()["title"].len(), 2);
+        assert!(errors.into_result().is_err());
+    }
+
+    // A database error as Postgres would report it
+    #[derive(Debug)]
+    struct PgError {
+        code: &'static str,
+        constraint: &'static str,
+    }
+
+    impl fmt::Display for PgError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "violates constraint {}", self.constraint)
+        }
+    }
+
+    impl Error for PgError {}
+
+    impl DatabaseError for PgError {
+        fn message(&self) -> &str {
+            "constraint violation"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.code))
+        }
+
+        fn constraint(&self) -> Option<&str> {
+            Some(self.constraint)
+        }
+
+        fn as_error(&self) -> &(dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn Error + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    fn db_error(code: &'static str, constraint: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(PgError { code, constraint }))
+    }
+
+    #[test]
+    fn duplicates_map_to_conflict() {
+        let err = duplicate_user(db_error("23505", "users_email_key"));
+        assert!(matches!(&err, ApiError::Conflict(message) if message.contains("Email")));
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+
+        let err = duplicate_user(db_error("23505", "users_username_key"));
+        assert!(matches!(&err, ApiError::Conflict(message) if message.contains("Username")));
+
+        // Any other unique violation is still a conflict, other errors are not
+        assert_eq!(ApiError::from(db_error("23505", "users_pkey")).status(), StatusCode::CONFLICT);
+        assert_eq!(
+            duplicate_user(db_error("23514", "todos_title_check")).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}